@@ -0,0 +1,15 @@
+//! The UDP response to StatusRequest::send, as parsed before it is decoded
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sr5900p::protocol::describe_packet;
+use sr5900p::protocol::PacketHeader;
+use sr5900p::protocol::StatusRequest;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = describe_packet(data);
+    let req = PacketHeader::new_request(StatusRequest::CMD, 0);
+    if let Ok((header, data)) = PacketHeader::parse_response(data, &req) {
+        let _ = StatusRequest::decode_status(header, data);
+    }
+});
@@ -0,0 +1,16 @@
+//! The analyzer and the decoder of the raster data sent to the printer
+//! should return an error on malformed data, instead of panicking.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sr5900p::analyzer::analyze_tcp_data_to;
+use sr5900p::analyzer::decode_tcp_data;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = analyze_tcp_data_to(data, &mut std::io::sink());
+    if let Ok(job) = decode_tcp_data(data) {
+        for head_offset_dots in [0, 16, 32] {
+            let _ = job.to_display(head_offset_dots);
+        }
+    }
+});
@@ -1,79 +1,210 @@
+use crate::commands::annotate;
+use crate::display::TapeDisplay;
 use anyhow::anyhow;
+use anyhow::Context;
 use anyhow::Result;
+use std::io::Write;
 use std::num::Wrapping;
 
 pub fn analyze_tcp_data(data: &[u8]) -> Result<()> {
-    println!("Size: {}", data.len());
+    analyze_tcp_data_to(data, &mut std::io::stdout().lock())
+}
+
+/// Same as analyze_tcp_data, but writes the result to w (e.g. a log file).
+/// Malformed or truncated data is reported as an error, and never panics.
+/// ESC { commands are annotated from the registry in commands.rs, and the
+/// ones which differ from the known captures are marked as UNSEEN.
+pub fn analyze_tcp_data_to<W: Write>(data: &[u8], w: &mut W) -> Result<()> {
+    writeln!(w, "Size: {}", data.len())?;
     let mut i = 0;
     let mut num_data_rows = 0;
+    let mut num_unseen_commands = 0;
     while i < data.len() {
-        match data[i] {
-            0x1b => match data[i + 1] {
-                0x7b => {
-                    let payload_data = &data[i..i + 3 + data[i + 2] as usize];
-                    println!("{payload_data:?}");
-                    i += payload_data.len();
-                    let mut payload_data = &payload_data[3..];
+        let (block, size) = parse_block(&data[i..])?;
+        match block {
+            Block::Command(cmd) => {
+                writeln!(w, "{:?}", &data[i..i + size])?;
+                let annotation = annotate(cmd);
+                writeln!(w, "cmd 0x1b 0x7b, {cmd:?} {}", annotation.text)?;
+                if annotation.unseen {
+                    num_unseen_commands += 1;
+                }
+            }
+            Block::Raster { bits, data } => {
+                write!(w, "cmd 0x1b 0x2e, bits = {bits}, bytes = {}: ", data.len())?;
+                for byte in data {
+                    write!(w, "{byte:08b}")?;
+                }
+                writeln!(w)?;
+                num_data_rows += 1;
+            }
+            Block::End => {
+                writeln!(w, "cmd 0x0c (data end marker?)",)?;
+            }
+        }
+        i += size;
+    }
+    writeln!(w, "num_data_rows = {}", num_data_rows)?;
+    writeln!(w, "num_unseen_commands = {}", num_unseen_commands)?;
+    Ok(())
+}
 
-                    if payload_data.last().unwrap() != &0x7d {
-                        return Err(anyhow!(
-                            "Unexpected label data (not 0x7d): {:?}...",
-                            &data[i..i + 16]
-                        ));
-                    }
-                    payload_data.take_last();
+/// Returns the tape length if cmd is the command 76
+fn parse_tape_len(cmd: &[u8]) -> Result<Option<u32>> {
+    match cmd {
+        [76, len @ ..] => {
+            let len: [u8; 4] = len
+                .try_into()
+                .map_err(|_| anyhow!("Unexpected tape length command: {cmd:?}"))?;
+            Ok(Some(u32::from_le_bytes(len)))
+        }
+        _ => Ok(None),
+    }
+}
 
-                    if payload_data
-                        .iter()
-                        .map(|v| Wrapping(*v))
-                        .sum::<Wrapping<u8>>()
-                        .0
-                        != payload_data.last().unwrap().wrapping_mul(2)
-                    {
-                        return Err(anyhow!(
-                            "Unexpected label data (csum invalid): {:?}...",
-                            &data[i..i + 16]
-                        ));
-                    }
-                    // so the last byte of the payload_data is the checksum
-                    payload_data.take_last();
-                    if payload_data[0] == 76 {
-                        let mut tape_len_bytes = [0u8; 4];
-                        tape_len_bytes.copy_from_slice(&payload_data[1..5]);
-                        let tape_len = u32::from_le_bytes(tape_len_bytes);
-                        println!("cmd 0x1b 0x7b, {payload_data:?} tape_len = {}", tape_len);
-                    } else {
-                        println!("cmd 0x1b 0x7b, {payload_data:?}");
-                    }
+/// A block of the data sent to the printer
+#[derive(Debug, PartialEq, Eq)]
+pub enum Block<'a> {
+    /// ESC { : a command, without the checksum and the closing 0x7d
+    Command(&'a [u8]),
+    /// ESC . : a raster row of bits pixels
+    Raster { bits: usize, data: &'a [u8] },
+    /// 0x0c (data end marker?)
+    End,
+}
+
+/// Parses the block at the start of data, and returns it with its size in bytes.
+/// The checksums of commands are verified.
+pub fn parse_block(data: &[u8]) -> Result<(Block<'_>, usize)> {
+    let context = || {
+        format!(
+            "Unexpected label data: {:?}...",
+            &data[..data.len().min(16)]
+        )
+    };
+    match data.first() {
+        Some(0x0c) => Ok((Block::End, 1)),
+        Some(0x1b) => match data.get(1) {
+            Some(0x7b) => {
+                let len = *data.get(2).with_context(context)? as usize;
+                let block = data.get(..3 + len).with_context(context)?;
+                let payload = match &block[3..] {
+                    [payload @ .., 0x7d] => payload,
+                    _ => return Err(anyhow!("Unexpected label data (not 0x7d): {block:?}")),
+                };
+                let (cmd, csum) = match payload {
+                    [cmd @ .., csum] => (cmd, *csum),
+                    [] => return Err(anyhow!("Unexpected label data (no csum): {block:?}")),
+                };
+                let sum = cmd.iter().map(|v| Wrapping(*v)).sum::<Wrapping<u8>>().0;
+                if sum != csum {
+                    return Err(anyhow!("Unexpected label data (csum invalid): {block:?}"));
                 }
-                0x2e => {
-                    if data[i + 2..i + 6] != [0, 0, 0, 1] {
-                        return Err(anyhow!("Unexpected label data: {:?}...", &data[i..i + 16]));
+                Ok((Block::Command(cmd), block.len()))
+            }
+            Some(0x2e) => {
+                let header = data.get(..8).with_context(context)?;
+                if header[2..6] != [0, 0, 0, 1] {
+                    return Err(anyhow!(context()));
+                }
+                let bits = u16::from_le_bytes([header[6], header[7]]) as usize;
+                let bytes = bits.div_ceil(8);
+                let data = data.get(8..8 + bytes).with_context(context)?;
+                Ok((Block::Raster { bits, data }, 8 + bytes))
+            }
+            _ => Err(anyhow!(context())),
+        },
+        _ => Err(anyhow!(context())),
+    }
+}
+
+/// A print job decoded from the data sent to the printer
+#[derive(Debug, Default)]
+pub struct DecodedJob {
+    /// Payloads of the ESC { commands, in the order they were sent
+    pub commands: Vec<Vec<u8>>,
+    /// The tape length sent with the command 76
+    pub tape_len: Option<u32>,
+    /// Raster rows as sent, from the end of the label: (bits, data)
+    pub rows: Vec<(usize, Vec<u8>)>,
+    /// Whether the data end marker was seen
+    pub ended: bool,
+}
+impl DecodedJob {
+    /// Reconstructs the bitmap that was encoded. The printable area starts
+    /// at head_offset_dots in each row, as in the geometry of the tape.
+    pub fn to_display(&self, head_offset_dots: usize) -> Result<TapeDisplay> {
+        let bits = self
+            .rows
+            .first()
+            .map(|(bits, _)| *bits)
+            .unwrap_or(head_offset_dots);
+        if self.rows.iter().any(|(b, _)| *b != bits) {
+            return Err(anyhow!("The raster rows have different lengths"));
+        }
+        let height = bits
+            .checked_sub(head_offset_dots)
+            .context("The raster rows are shorter than the head offset")?;
+        let width = self.rows.len();
+        let mut td = TapeDisplay::new(width, height);
+        for (i, (_, data)) in self.rows.iter().enumerate() {
+            for (pos, byte) in data.iter().enumerate() {
+                for dx in 0..8 {
+                    let p = pos * 8 + dx;
+                    if byte & (0x80 >> dx) == 0 {
+                        continue;
                     }
-                    let bits = data[i + 6] as usize + data[i + 7] as usize * 256;
-                    let bytes = (bits + 7) / 8;
-                    print!("cmd 0x1b 0x2e, bits = {bits}, bytes = {bytes}: ",);
-                    let img_data = &data[i + 8..i + 8 + bytes];
-                    for byte in img_data {
-                        print!("{byte:08b}");
+                    if p < head_offset_dots || p >= bits {
+                        return Err(anyhow!("Ink outside of the printable area in row {i}"));
                     }
-                    println!();
-                    i += 8 + bytes;
-                    num_data_rows += 1;
-                }
-                _ => {
-                    return Err(anyhow!("Unexpected label data: {:?}...", &data[i..i + 16]));
+                    // The rows are sent from the end of the label
+                    td.set_pixel(width - 1 - i, p - head_offset_dots, true);
                 }
-            },
-            0x0c => {
-                println!("cmd 0x0c (data end marker?)",);
-                i += 1;
             }
-            _ => {
-                return Err(anyhow!("Unexpected label data: {:?}...", &data[i..]));
+        }
+        Ok(td)
+    }
+}
+
+/// Decodes the data generated by gen_tcp_data
+pub fn decode_tcp_data(data: &[u8]) -> Result<DecodedJob> {
+    let mut job = DecodedJob::default();
+    let mut i = 0;
+    while i < data.len() {
+        let (block, size) = parse_block(&data[i..])?;
+        match block {
+            Block::Command(cmd) => {
+                if let Some(tape_len) = parse_tape_len(cmd)? {
+                    job.tape_len = Some(tape_len);
+                }
+                job.commands.push(cmd.to_vec());
             }
+            Block::Raster { bits, data } => job.rows.push((bits, data.to_vec())),
+            Block::End => job.ended = true,
         }
+        i += size;
+    }
+    Ok(job)
+}
+
+#[cfg(test)]
+proptest::proptest! {
+    #[test]
+    fn analyzer_is_total(garbage: Vec<u8>, cut: usize, flip: (usize, u8)) {
+        let analyze = |data: &[u8]| {
+            let _ = analyze_tcp_data_to(data, &mut std::io::sink());
+            let _ = decode_tcp_data(data).and_then(|job| job.to_display(16));
+        };
+        analyze(&garbage);
+        // a valid job, truncated or with a corrupted byte
+        let geometry = crate::Tape::W12.geometry();
+        let mut td = TapeDisplay::new(10, 20);
+        td.set_pixel(3, 5, true);
+        let data = crate::print::gen_tcp_data(&td, &geometry).unwrap();
+        proptest::prop_assert!(analyze_tcp_data_to(&data, &mut std::io::sink()).is_ok());
+        analyze(&data[..cut % data.len()]);
+        let mut corrupted = data.clone();
+        corrupted[flip.0 % data.len()] ^= flip.1;
+        analyze(&corrupted);
     }
-    println!("num_data_rows = {}", num_data_rows);
-    Ok(())
 }
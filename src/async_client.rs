@@ -0,0 +1,194 @@
+use crate::encoder::JobEncoder;
+use crate::encoder::RowSource;
+use crate::protocol::check_data_stream_response;
+use crate::protocol::printer_addr;
+use crate::protocol::udp_bind_addr;
+use crate::protocol::Codec;
+use crate::protocol::PacketHeader;
+use crate::protocol::StartPrintRequest;
+use crate::protocol::StatusRequest;
+use crate::protocol::StopPrintRequest;
+use crate::protocol::NOTIFY_DATA_STREAM_CMDS;
+use crate::PrinterStatus;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::io::BufWriter;
+use tokio::net::TcpStream;
+use tokio::net::UdpSocket;
+use tokio::time::sleep;
+use tokio::time::timeout;
+
+/// How long to wait for a response to a UDP command
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+/// The wait between the steps of opening a session, and the interval of
+/// the status polling while printing (same as the blocking PrintSession)
+const STEP_INTERVAL: Duration = Duration::from_millis(500);
+
+/// An async client for the UDP commands of the printer. The requests and
+/// the responses are encoded and checked with the same code as the
+/// blocking functions in protocol.rs.
+pub struct AsyncPrinter {
+    addr: String,
+    socket: UdpSocket,
+}
+impl AsyncPrinter {
+    /// device_ip can be "host:port" as well, e.g. for an emulator
+    pub async fn new(device_ip: &str) -> Result<Self> {
+        let socket = UdpSocket::bind(udp_bind_addr(device_ip))
+            .await
+            .context("failed to bind")?;
+        Ok(Self {
+            addr: printer_addr(device_ip),
+            socket,
+        })
+    }
+    /// Sends a request of cmd without data, and returns the validated response.
+    async fn send_request(&self, cmd: u32) -> Result<(PacketHeader, Vec<u8>)> {
+        let req = PacketHeader::new_request(cmd, 0);
+        self.socket
+            .send_to(&req.encode(), self.addr.as_str())
+            .await
+            .context("failed to send")?;
+        let mut buf = [0; 128];
+        let (len, _) = timeout(RESPONSE_TIMEOUT, self.socket.recv_from(&mut buf))
+            .await
+            .map_err(|_| anyhow!("No response for cmd {cmd:#06X} from {}", self.addr))??;
+        let (header, data) = PacketHeader::parse_response(&buf[..len], &req)?;
+        Ok((header, data.to_vec()))
+    }
+    pub async fn status(&self) -> Result<PrinterStatus> {
+        let (res_header, data) = self.send_request(StatusRequest::CMD).await?;
+        StatusRequest::decode_status(res_header, &data)
+    }
+    pub async fn start_print(&self) -> Result<()> {
+        let (res_header, data) = self.send_request(StartPrintRequest::CMD).await?;
+        StartPrintRequest::check_response(res_header, &data)
+    }
+    /// Tells the printer that the data will be sent on the TCP connection
+    pub async fn notify_data_stream(&self) -> Result<()> {
+        let [prepare, notify] = NOTIFY_DATA_STREAM_CMDS;
+        self.send_request(prepare).await?;
+        let (res_header, data) = self.send_request(notify).await?;
+        check_data_stream_response(res_header, &data)
+    }
+    pub async fn stop_print(&self) -> Result<()> {
+        let (res_header, data) = self.send_request(StopPrintRequest::CMD).await?;
+        StopPrintRequest::check_response(res_header, &data)
+    }
+    /// Polls the status until the printer is not printing, and returns the last status
+    pub async fn wait_until_printed(&self) -> Result<PrinterStatus> {
+        loop {
+            sleep(STEP_INTERVAL).await;
+            match self.status().await? {
+                PrinterStatus::Printing => continue,
+                status => return Ok(status),
+            }
+        }
+    }
+}
+
+/// The async counterpart of print::PrintSession
+pub struct AsyncPrintSession {
+    printer: AsyncPrinter,
+    stream: TcpStream,
+}
+impl AsyncPrintSession {
+    pub async fn open(device_ip: &str) -> Result<Self> {
+        let printer = AsyncPrinter::new(device_ip).await?;
+        match printer.status().await? {
+            PrinterStatus::SomeTape(..) | PrinterStatus::UnknownTape(..) => {}
+            info => return Err(anyhow!("Unexpected state: {:?}. Aborting...", info)),
+        }
+        printer.start_print().await?;
+        sleep(STEP_INTERVAL).await;
+        let stream = TcpStream::connect(printer.addr.as_str()).await?;
+        sleep(STEP_INTERVAL).await;
+        printer.notify_data_stream().await?;
+        sleep(STEP_INTERVAL).await;
+        Ok(Self { printer, stream })
+    }
+    pub fn printer(&self) -> &AsyncPrinter {
+        &self.printer
+    }
+    /// Writes a label generated by gen_tcp_data without waiting for the printer
+    pub async fn send_data(&mut self, data: &[u8]) -> Result<()> {
+        self.stream.write_all(data).await?;
+        self.stream.flush().await?;
+        Ok(())
+    }
+    /// Writes a label while it is encoded. The rows are encoded only as fast
+    /// as the printer receives them.
+    pub async fn send_stream<S: RowSource>(&mut self, job: JobEncoder<S>) -> Result<()> {
+        let mut w = BufWriter::new(&mut self.stream);
+        for chunk in job {
+            w.write_all(&chunk).await?;
+        }
+        w.flush().await?;
+        Ok(())
+    }
+    /// Sends a label and waits for the printer. Returns the status after
+    /// printing, as PrintSession::print does.
+    pub async fn print(&mut self, data: &[u8]) -> Result<PrinterStatus> {
+        self.send_data(data).await?;
+        self.printer.wait_until_printed().await
+    }
+    pub async fn close(self) -> Result<()> {
+        self.printer.stop_print().await
+    }
+    /// Closes the session after an error, as PrintSession::abort does
+    pub async fn abort(self, e: anyhow::Error) -> anyhow::Error {
+        if let Err(close_error) = self.close().await {
+            eprintln!("Failed to close the print session: {close_error:#}");
+        }
+        e
+    }
+}
+
+/// Prints a label generated by gen_tcp_data in a session of its own
+pub async fn print_tcp_data(device_ip: &str, data: &[u8]) -> Result<()> {
+    let mut session = AsyncPrintSession::open(device_ip).await?;
+    match session.print(data).await {
+        Ok(_) => session.close().await,
+        Err(e) => Err(session.abort(e).await),
+    }
+}
+
+#[test]
+fn print_with_emulator() {
+    use crate::display::TapeDisplay;
+    use crate::emulator::Emulator;
+    use crate::print::gen_tcp_data;
+    use crate::Tape;
+    use std::time::Instant;
+
+    let emulator = Emulator::start("127.0.0.1:0", Tape::W12, None).unwrap();
+    let printer = emulator.addr().to_string();
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let data = gen_tcp_data(&TapeDisplay::new(32, 16), &Tape::W12.geometry()).unwrap();
+    rt.block_on(async {
+        let status = AsyncPrinter::new(&printer).await?.status().await?;
+        assert!(matches!(status, PrinterStatus::SomeTape(Tape::W12, _)));
+        print_tcp_data(&printer, &data).await
+    })
+    .unwrap();
+    // the session is recorded when the connection is closed
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while emulator.sessions().is_empty() {
+        assert!(Instant::now() < deadline);
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    assert_eq!(emulator.sessions(), [data]);
+
+    // the session is closed even if the data can't be sent
+    emulator.fail_data(true);
+    let data = vec![0u8; 32 << 20];
+    assert!(rt.block_on(print_tcp_data(&printer, &data)).is_err());
+    assert_eq!(emulator.commands().last(), Some(&StopPrintRequest::CMD));
+    assert_eq!(emulator.sessions().len(), 1);
+}
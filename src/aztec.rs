@@ -0,0 +1,394 @@
+use crate::display::TapeDisplay;
+use crate::reed_solomon::Galois;
+use anyhow::anyhow;
+use anyhow::Result;
+
+/// Percentage of the symbol used for error correction (the default of ISO/IEC 24778)
+const ECC_PERCENT: usize = 33;
+const MAX_LAYERS: usize = 32;
+/// Binary Shift in the Upper mode, which the encoding starts in
+const BINARY_SHIFT: u32 = 31;
+
+fn push_bits(bits: &mut Vec<bool>, value: u32, n: usize) {
+    bits.extend((0..n).rev().map(|i| (value >> i) & 1 != 0));
+}
+
+fn to_word(bits: &[bool]) -> u16 {
+    bits.iter().fold(0, |w, b| (w << 1) | *b as u16)
+}
+
+fn galois(word_size: usize) -> Galois {
+    match word_size {
+        4 => Galois::new(4, 0x13),
+        6 => Galois::new(6, 0x43),
+        8 => Galois::new(8, 0x12D),
+        10 => Galois::new(10, 0x409),
+        12 => Galois::new(12, 0x1069),
+        _ => unreachable!(),
+    }
+}
+
+fn word_size(layers: usize) -> usize {
+    match layers {
+        1..=2 => 6,
+        3..=8 => 8,
+        9..=22 => 10,
+        _ => 12,
+    }
+}
+
+fn total_bits_in_layers(layers: usize, compact: bool) -> usize {
+    ((if compact { 88 } else { 112 }) + 16 * layers) * layers
+}
+
+/// Encodes the bytes with Binary Shift, which is not the shortest for text
+/// but can encode anything
+fn encode_bytes(data: &[u8]) -> Vec<bool> {
+    let mut bits = Vec::new();
+    for chunk in data.chunks(2047 + 31) {
+        let n = chunk.len() as u32;
+        if n <= 31 {
+            push_bits(&mut bits, BINARY_SHIFT, 5);
+            push_bits(&mut bits, n, 5);
+        } else if n <= 62 {
+            // two shifts are shorter than the long form
+            push_bits(&mut bits, BINARY_SHIFT, 5);
+            push_bits(&mut bits, 31, 5);
+            for b in &chunk[..31] {
+                push_bits(&mut bits, *b as u32, 8);
+            }
+            push_bits(&mut bits, BINARY_SHIFT, 5);
+            push_bits(&mut bits, n - 31, 5);
+            for b in &chunk[31..] {
+                push_bits(&mut bits, *b as u32, 8);
+            }
+            continue;
+        } else {
+            push_bits(&mut bits, BINARY_SHIFT, 5);
+            push_bits(&mut bits, 0, 5);
+            push_bits(&mut bits, n - 31, 11);
+        }
+        for b in chunk {
+            push_bits(&mut bits, *b as u32, 8);
+        }
+    }
+    bits
+}
+
+/// Splits the bits into words, avoiding words of all zeros or all ones
+fn stuff_bits(bits: &[bool], word_size: usize) -> Vec<bool> {
+    let mut out = Vec::new();
+    let mask = (1u32 << word_size) - 2;
+    let mut i = 0;
+    while i < bits.len() {
+        // the last word is padded with ones
+        let word = (0..word_size).fold(0u32, |w, j| {
+            (w << 1) | bits.get(i + j).copied().unwrap_or(true) as u32
+        });
+        if word & mask == mask {
+            push_bits(&mut out, word & mask, word_size);
+            i += word_size - 1;
+        } else if word & mask == 0 {
+            push_bits(&mut out, word | 1, word_size);
+            i += word_size - 1;
+        } else {
+            push_bits(&mut out, word, word_size);
+            i += word_size;
+        }
+    }
+    out
+}
+
+/// Appends the check words to fill total_bits, with zeros at the start for
+/// the remainder
+fn add_check_words(bits: &[bool], total_bits: usize, word_size: usize) -> Vec<bool> {
+    let words: Vec<u16> = bits.chunks(word_size).map(to_word).collect();
+    let ecc = galois(word_size).check_words(&words, total_bits / word_size - words.len());
+    let mut out = vec![false; total_bits % word_size];
+    for w in words.iter().chain(&ecc) {
+        push_bits(&mut out, *w as u32, word_size);
+    }
+    out
+}
+
+fn mode_message(compact: bool, layers: usize, data_words: usize) -> Vec<bool> {
+    let mut bits = Vec::new();
+    if compact {
+        push_bits(&mut bits, layers as u32 - 1, 2);
+        push_bits(&mut bits, data_words as u32 - 1, 6);
+        add_check_words(&bits, 28, 4)
+    } else {
+        push_bits(&mut bits, layers as u32 - 1, 5);
+        push_bits(&mut bits, data_words as u32 - 1, 11);
+        add_check_words(&bits, 40, 4)
+    }
+}
+
+/// The layout of an Aztec symbol, as in the encoder of ZXing
+struct Symbol {
+    compact: bool,
+    layers: usize,
+}
+impl Symbol {
+    /// Size without the reference grid
+    fn base_size(&self) -> usize {
+        if self.compact {
+            11 + self.layers * 4
+        } else {
+            14 + self.layers * 4
+        }
+    }
+    fn size(&self) -> usize {
+        let base = self.base_size();
+        if self.compact {
+            base
+        } else {
+            base + 1 + 2 * ((base / 2 - 1) / 15)
+        }
+    }
+    /// Positions of the modules without the lines of the reference grid
+    fn alignment_map(&self) -> Vec<usize> {
+        let base = self.base_size();
+        if self.compact {
+            return (0..base).collect();
+        }
+        let mut map = vec![0; base];
+        let (orig_center, center) = (base / 2, self.size() / 2);
+        for i in 0..orig_center {
+            let ofs = i + i / 15;
+            map[orig_center - i - 1] = center - ofs - 1;
+            map[orig_center + i] = center + ofs + 1;
+        }
+        map
+    }
+    /// (x, y) of each bit of the data, spiraling from the outermost layer
+    fn data_positions(&self) -> Vec<(usize, usize)> {
+        let (map, base) = (self.alignment_map(), self.base_size());
+        let mut positions = vec![(0, 0); total_bits_in_layers(self.layers, self.compact)];
+        let mut row_ofs = 0;
+        for i in 0..self.layers {
+            let row_size = (self.layers - i) * 4 + if self.compact { 9 } else { 12 };
+            for j in 0..row_size {
+                let col_ofs = j * 2;
+                for k in 0..2 {
+                    let ofs = row_ofs + col_ofs + k;
+                    positions[ofs] = (map[i * 2 + k], map[i * 2 + j]);
+                    positions[ofs + row_size * 2] = (map[i * 2 + j], map[base - 1 - i * 2 - k]);
+                    positions[ofs + row_size * 4] =
+                        (map[base - 1 - i * 2 - k], map[base - 1 - i * 2 - j]);
+                    positions[ofs + row_size * 6] = (map[base - 1 - i * 2 - j], map[i * 2 + k]);
+                }
+            }
+            row_ofs += row_size * 8;
+        }
+        positions
+    }
+    /// (x, y) of each bit of the mode message, around the bull's eye
+    fn mode_message_positions(&self) -> Vec<(usize, usize)> {
+        let center = self.size() / 2;
+        let (n, r) = if self.compact { (7, 5) } else { (10, 7) };
+        let mut positions = vec![(0, 0); n * 4];
+        for i in 0..n {
+            let ofs = if self.compact {
+                center - 3 + i
+            } else {
+                center - 5 + i + i / 5
+            };
+            positions[i] = (ofs, center - r);
+            positions[i + n] = (center + r, ofs);
+            positions[n * 3 - 1 - i] = (ofs, center + r);
+            positions[n * 4 - 1 - i] = (center - r, ofs);
+        }
+        positions
+    }
+    fn draw_finder(&self, td: &mut TapeDisplay) {
+        let (size, center) = (self.size(), self.size() / 2);
+        let r = if self.compact { 5 } else { 7 };
+        // the bull's eye
+        for i in (0..r).step_by(2) {
+            for j in center - i..=center + i {
+                td.set_pixel(j, center - i, true);
+                td.set_pixel(j, center + i, true);
+                td.set_pixel(center - i, j, true);
+                td.set_pixel(center + i, j, true);
+            }
+        }
+        // the orientation marks
+        for (x, y) in [
+            (center - r, center - r),
+            (center - r + 1, center - r),
+            (center - r, center - r + 1),
+            (center + r, center - r),
+            (center + r, center - r + 1),
+            (center + r, center + r - 1),
+        ] {
+            td.set_pixel(x, y, true);
+        }
+        if !self.compact {
+            // the reference grid
+            let mut j = 0;
+            while j / 16 * 15 < self.base_size() / 2 - 1 {
+                for k in (center & 1..size).step_by(2) {
+                    td.set_pixel(center - j, k, true);
+                    td.set_pixel(center + j, k, true);
+                    td.set_pixel(k, center - j, true);
+                    td.set_pixel(k, center + j, true);
+                }
+                j += 16;
+            }
+        }
+    }
+}
+
+/// Encodes the text as an Aztec code in the smallest size, preferring
+/// compact symbols. Returns the modules as pixels.
+pub fn encode(text: &str) -> Result<TapeDisplay> {
+    if text.is_empty() {
+        return Err(anyhow!("The text for an Aztec code is empty"));
+    }
+    let bits = encode_bytes(text.as_bytes());
+    let ecc_bits = bits.len() * ECC_PERCENT / 100 + 11;
+    let mut stuffed: Option<(usize, Vec<bool>)> = None;
+    // compact symbols of 1 to 4 layers, and then full symbols of 4 or more
+    for (compact, layers) in (1..=4)
+        .map(|l| (true, l))
+        .chain((4..=MAX_LAYERS).map(|l| (false, l)))
+    {
+        let total_bits = total_bits_in_layers(layers, compact);
+        if bits.len() + ecc_bits > total_bits {
+            continue;
+        }
+        let word_size = word_size(layers);
+        if stuffed.as_ref().map(|(ws, _)| *ws) != Some(word_size) {
+            stuffed = Some((word_size, stuff_bits(&bits, word_size)));
+        }
+        let data = &stuffed.as_ref().unwrap().1;
+        // the mode message of compact symbols has 6 bits for the number of words
+        if compact && data.len() > word_size * 64 {
+            continue;
+        }
+        if data.len() + ecc_bits > total_bits - total_bits % word_size {
+            continue;
+        }
+        let symbol = Symbol { compact, layers };
+        let mut td = TapeDisplay::new(symbol.size(), symbol.size());
+        let message = add_check_words(data, total_bits, word_size);
+        for (bit, (x, y)) in message.iter().zip(symbol.data_positions()) {
+            td.set_pixel(x, y, *bit);
+        }
+        let mode = mode_message(compact, layers, data.len() / word_size);
+        for (bit, (x, y)) in mode.iter().zip(symbol.mode_message_positions()) {
+            td.set_pixel(x, y, *bit);
+        }
+        symbol.draw_finder(&mut td);
+        return Ok(td);
+    }
+    Err(anyhow!(
+        "The text ({} bytes) does not fit an Aztec code",
+        text.len()
+    ))
+}
+
+#[test]
+fn aztec() {
+    // the mode messages in the tests of ZXing
+    let bits =
+        |s: &str| -> Vec<bool> { s.chars().filter(|c| *c != ' ').map(|c| c == 'X').collect() };
+    assert_eq!(
+        mode_message(true, 2, 29),
+        bits(".X .XXX.. ...X XX.. ..X .XX. .XX.X")
+    );
+    assert_eq!(
+        mode_message(true, 4, 64),
+        bits("XX XXXXXX .X.. ...X ..XX .X.. XX..")
+    );
+    assert_eq!(
+        mode_message(false, 21, 660),
+        bits("X.X.. .X.X..X..XX .XXX ..X.. .XXX. .X... ..XXX")
+    );
+    assert_eq!(
+        stuff_bits(&bits(".X.X. ..... .X.X"), 5),
+        bits(".X.X. ....X ..X.X")
+    );
+
+    for text in ["A", "Hello, world!", &"Aztec ".repeat(10), &"x".repeat(500)] {
+        let td = encode(text).unwrap();
+        // find the symbol from its size
+        let symbol = (1..=4)
+            .map(|l| Symbol {
+                compact: true,
+                layers: l,
+            })
+            .chain((4..=MAX_LAYERS).map(|l| Symbol {
+                compact: false,
+                layers: l,
+            }))
+            .find(|s| s.size() == td.width)
+            .unwrap();
+        let center = td.width / 2;
+        assert!(td.get_pixel(center, center));
+        assert!(!td.get_pixel(center + 1, center));
+        assert!(td.get_pixel(center + 2, center));
+
+        // the data and the mode message do not overlap each other nor the finder
+        let mut used = TapeDisplay::new(td.width, td.height);
+        symbol.draw_finder(&mut used);
+        for (x, y) in symbol
+            .data_positions()
+            .iter()
+            .chain(&symbol.mode_message_positions())
+        {
+            assert!(!used.get_pixel(*x, *y));
+            used.set_pixel(*x, *y, true);
+        }
+
+        let read = |positions: Vec<(usize, usize)>| -> Vec<bool> {
+            positions
+                .iter()
+                .map(|(x, y)| td.get_pixel(*x, *y))
+                .collect()
+        };
+        let mode = read(symbol.mode_message_positions());
+        let mode_words: Vec<u16> = mode.chunks(4).map(to_word).collect();
+        let (n, words_bits) = if symbol.compact { (5, 6) } else { (6, 11) };
+        assert!(galois(4).is_valid(&mode_words, n));
+        let v = to_word(&mode[..mode_words.len() * 4 - n * 4]);
+        let (layers, data_words) = (v >> words_bits, v & ((1 << words_bits) - 1));
+        assert_eq!(layers as usize + 1, symbol.layers);
+
+        let word_size = word_size(symbol.layers);
+        let message = read(symbol.data_positions());
+        let words: Vec<u16> = message[message.len() % word_size..]
+            .chunks(word_size)
+            .map(to_word)
+            .collect();
+        assert!(galois(word_size).is_valid(&words, words.len() - data_words as usize - 1));
+        // unstuff and decode the binary shifts
+        let mut bits = Vec::new();
+        for w in &words[..data_words as usize + 1] {
+            let w = *w as u32;
+            let n = if w >> 1 == 0 || w >> 1 == (1 << (word_size - 1)) - 1 {
+                word_size - 1
+            } else {
+                word_size
+            };
+            push_bits(&mut bits, w >> (word_size - n), n);
+        }
+        let mut decoded = Vec::new();
+        let mut bits = &bits[..];
+        while decoded.len() < text.len() {
+            assert_eq!(to_word(&bits[..5]), BINARY_SHIFT as u16);
+            let mut n = to_word(&bits[5..10]) as usize;
+            bits = &bits[10..];
+            if n == 0 {
+                n = to_word(&bits[..11]) as usize + 31;
+                bits = &bits[11..];
+            }
+            for b in bits[..n * 8].chunks(8) {
+                decoded.push(to_word(b) as u8);
+            }
+            bits = &bits[n * 8..];
+        }
+        assert_eq!(decoded, text.as_bytes());
+    }
+}
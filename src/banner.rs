@@ -0,0 +1,247 @@
+use crate::display::TapeDisplay;
+use crate::encoder::RowSource;
+use crate::layout::LengthOptions;
+use crate::print::mm_to_px;
+use anyhow::anyhow;
+use anyhow::Result;
+use embedded_graphics::mono_font::ascii::FONT_10X20;
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::Point;
+use embedded_graphics::text::Baseline;
+use embedded_graphics::text::Text;
+use embedded_graphics::Drawable;
+use std::ops::Range;
+
+const GLYPH_WIDTH: usize = 10;
+const GLYPH_HEIGHT: usize = 20;
+
+/// The maximum length of a job when it is not given. The limit of the
+/// printer is not known yet, so this is a conservative guess.
+pub const DEFAULT_MAX_JOB_LENGTH_MM: f32 = 1000.0;
+/// Blank tape with a cut mark, added at the joints of the segments
+const JOINT_MM: f32 = 5.0;
+
+/// A line of large text as tall as the tape. Glyphs are rendered only when
+/// their rows are requested, so a banner of any length can be printed
+/// without building the whole bitmap.
+pub struct Banner {
+    text: Vec<char>,
+    scale: usize,
+    height: usize,
+    width: usize,
+    /// Offset of the text in the label (from LengthOptions)
+    ofs_x: usize,
+    ofs_y: usize,
+    /// The last rendered glyph, since the rows are requested in order
+    glyph: Option<(usize, TapeDisplay)>,
+}
+impl Banner {
+    pub fn new(text: &str, tape_width_px: usize, length: &LengthOptions) -> Result<Self> {
+        let scale = tape_width_px / GLYPH_HEIGHT;
+        if scale == 0 {
+            return Err(anyhow!("The tape is too narrow for a banner"));
+        }
+        let text: Vec<char> = text.chars().collect();
+        let (width, ofs_x) = length.layout(text.len() * GLYPH_WIDTH * scale)?;
+        Ok(Self {
+            text,
+            scale,
+            height: tape_width_px,
+            width,
+            ofs_x,
+            ofs_y: (tape_width_px - GLYPH_HEIGHT * scale) / 2,
+            glyph: None,
+        })
+    }
+    fn glyph(&mut self, index: usize) -> &TapeDisplay {
+        if self.glyph.as_ref().map(|(i, _)| *i) != Some(index) {
+            let mut td = TapeDisplay::new(GLYPH_WIDTH, GLYPH_HEIGHT);
+            let style = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
+            let mut s = [0u8; 4];
+            Text::with_baseline(
+                self.text[index].encode_utf8(&mut s),
+                Point::zero(),
+                style,
+                Baseline::Top,
+            )
+            .draw(&mut td)
+            .unwrap_or_else(|e| match e {});
+            self.glyph = Some((index, td));
+        }
+        &self.glyph.as_ref().unwrap().1
+    }
+    /// Splits the banner into segments of at most max_px, including the
+    /// blank tape with cut marks at the joints.
+    pub fn segments(&self, max_px: usize) -> Result<Vec<Segment>> {
+        if self.width <= max_px {
+            return Ok(vec![Segment {
+                range: 0..self.width,
+                lead_in: 0,
+                lead_out: 0,
+            }]);
+        }
+        let joint = mm_to_px(JOINT_MM) as usize;
+        let step = max_px.saturating_sub(2 * joint);
+        if step < joint {
+            return Err(anyhow!(
+                "The maximum length is too short to split the banner into segments"
+            ));
+        }
+        let mut segments = Vec::new();
+        let mut start = 0;
+        while start < self.width {
+            let end = std::cmp::min(start + step, self.width);
+            segments.push(Segment {
+                range: start..end,
+                lead_in: if start == 0 { 0 } else { joint },
+                lead_out: if end == self.width { 0 } else { joint },
+            });
+            start = end;
+        }
+        Ok(segments)
+    }
+    pub fn segment(&mut self, segment: &Segment) -> BannerSegment<'_> {
+        BannerSegment {
+            banner: self,
+            segment: segment.clone(),
+        }
+    }
+}
+impl RowSource for Banner {
+    fn width(&self) -> usize {
+        self.width
+    }
+    fn height(&self) -> usize {
+        self.height
+    }
+    fn fill_row(&mut self, x: usize, row: &mut [bool]) {
+        row.fill(false);
+        let text_width = self.text.len() * GLYPH_WIDTH * self.scale;
+        let Some(tx) = x.checked_sub(self.ofs_x).filter(|tx| *tx < text_width) else {
+            return;
+        };
+        let (scale, ofs_y) = (self.scale, self.ofs_y);
+        let gx = tx / scale % GLYPH_WIDTH;
+        let glyph = self.glyph(tx / scale / GLYPH_WIDTH);
+        for (gy, p) in row[ofs_y..]
+            .chunks_mut(scale)
+            .take(GLYPH_HEIGHT)
+            .enumerate()
+        {
+            p.fill(glyph.get_pixel(gx, gy));
+        }
+    }
+}
+
+/// A part of a banner printed as a job
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Segment {
+    /// The range of the banner in this segment
+    pub range: Range<usize>,
+    /// Blank px before and after the range, with a cut mark next to the range
+    pub lead_in: usize,
+    pub lead_out: usize,
+}
+
+pub struct BannerSegment<'a> {
+    banner: &'a mut Banner,
+    segment: Segment,
+}
+impl RowSource for BannerSegment<'_> {
+    fn width(&self) -> usize {
+        self.segment.lead_in + self.segment.range.len() + self.segment.lead_out
+    }
+    fn height(&self) -> usize {
+        self.banner.height
+    }
+    fn fill_row(&mut self, x: usize, row: &mut [bool]) {
+        let Segment {
+            range,
+            lead_in,
+            lead_out,
+        } = &self.segment;
+        if let Some(i) = x.checked_sub(*lead_in).filter(|i| *i < range.len()) {
+            self.banner.fill_row(range.start + i, row);
+            return;
+        }
+        // A dashed line to cut along, right next to the banner, so that the
+        // segments can be joined without a gap
+        let is_mark =
+            (*lead_in > 0 && x + 1 == *lead_in) || (*lead_out > 0 && x == lead_in + range.len());
+        for (y, p) in row.iter_mut().enumerate() {
+            *p = is_mark && y % 8 < 4;
+        }
+    }
+}
+
+#[test]
+fn banner_segments() {
+    use crate::encoder::render_rows;
+    use crate::encoder::JobEncoder;
+    use crate::layout::Align;
+    use crate::layout::Mm;
+    use crate::print::gen_tcp_data;
+    use crate::Tape;
+
+    let geometry = Tape::W12.geometry();
+    let px = geometry.printable_px();
+    let mut banner = Banner::new("HELLO, WORLD", px, &LengthOptions::default()).unwrap();
+    let scale = px / GLYPH_HEIGHT;
+    assert_eq!(banner.width(), 12 * GLYPH_WIDTH * scale);
+    // not split if it fits
+    let segments = banner.segments(banner.width()).unwrap();
+    assert_eq!(segments.len(), 1);
+    let whole: Vec<u8> = JobEncoder::new(&mut banner, &geometry)
+        .unwrap()
+        .flatten()
+        .collect();
+    assert!(!whole.is_empty());
+    // streamed, it is the same job as the rendered bitmap
+    let td = render_rows(&mut banner);
+    assert_eq!(whole, gen_tcp_data(&td, &geometry).unwrap());
+    // the margins are added as LengthOptions::apply does for a bitmap
+    let length = LengthOptions {
+        margin_left: Some(Mm(3.0)),
+        min_length: Some(Mm(100.0)),
+        align: Align::Right,
+        ..Default::default()
+    };
+    let with_margins = render_rows(Banner::new("HELLO, WORLD", px, &length).unwrap());
+    let expected = length.apply(&td).unwrap();
+    assert_eq!(with_margins.width, expected.width);
+    assert!(with_margins.framebuffer == expected.framebuffer);
+
+    let max_px = mm_to_px(50.0) as usize;
+    let segments = banner.segments(max_px).unwrap();
+    assert!(segments.len() > 1);
+    // the segments cover the banner without gaps nor overlaps
+    assert_eq!(segments[0].range.start, 0);
+    assert_eq!(segments.last().unwrap().range.end, banner.width());
+    for (a, b) in segments.iter().zip(&segments[1..]) {
+        assert_eq!(a.range.end, b.range.start);
+    }
+    let mut rows = Vec::new();
+    for s in &segments {
+        let mut segment = banner.segment(s);
+        assert!(segment.width() <= max_px);
+        let mut row = vec![false; px];
+        for x in 0..segment.width() {
+            segment.fill_row(x, &mut row);
+            if x >= s.lead_in && x < s.lead_in + s.range.len() {
+                rows.push(row.clone());
+            }
+        }
+        // the cut marks
+        if s.lead_out > 0 {
+            segment.fill_row(s.lead_in + s.range.len(), &mut row);
+            assert!(row[0] && !row[px - 1]);
+        }
+    }
+    // the segments joined give the same image as the whole banner
+    let mut row = vec![false; px];
+    for (x, r) in rows.iter().enumerate() {
+        banner.fill_row(x, &mut row);
+        assert_eq!(&row, r);
+    }
+}
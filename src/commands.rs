@@ -0,0 +1,201 @@
+//! Known ESC { commands. Most of them are copied from captures of the vendor
+//! software and their meanings are not known, so each command records how
+//! sure we are and which parameter values have been seen, to spot the
+//! differences between captures.
+
+use std::fmt;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Confidence {
+    /// Verified by printing with other values
+    Confirmed,
+    /// Consistent with the captures, but not verified
+    Likely,
+    /// Only a name for the bytes
+    Guess,
+}
+impl fmt::Display for Confidence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Confidence::Confirmed => "confirmed",
+            Confidence::Likely => "likely",
+            Confidence::Guess => "guess",
+        })
+    }
+}
+
+/// A little-endian parameter of a command
+pub struct Field {
+    pub name: &'static str,
+    /// 1, 2 or 4 bytes
+    pub size: usize,
+    /// Values in the captures. Empty if any value is expected.
+    pub seen: &'static [u32],
+}
+
+pub struct Command {
+    pub id: u8,
+    pub meaning: &'static str,
+    pub confidence: Confidence,
+    pub fields: &'static [Field],
+}
+impl Command {
+    /// Length of the parameters after the id, in bytes
+    pub fn len(&self) -> usize {
+        self.fields.iter().map(|f| f.size).sum()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+}
+
+const fn field(name: &'static str, size: usize, seen: &'static [u32]) -> Field {
+    Field { name, size, seen }
+}
+
+pub const COMMANDS: &[Command] = &[
+    Command {
+        id: 64,
+        meaning: "start / end of a job",
+        confidence: Confidence::Likely,
+        fields: &[],
+    },
+    Command {
+        id: 123,
+        meaning: "unknown",
+        confidence: Confidence::Guess,
+        fields: &[
+            field("unknown0", 1, &[0]),
+            field("unknown1", 1, &[0]),
+            // "ST"
+            field("tag", 2, &[0x5453]),
+        ],
+    },
+    Command {
+        id: 67,
+        meaning: "cut options (half cut?)",
+        confidence: Confidence::Guess,
+        fields: &[
+            field("unknown0", 1, &[2]),
+            field("unknown1", 1, &[2]),
+            field("unknown2", 1, &[1]),
+            field("unknown3", 1, &[1]),
+        ],
+    },
+    Command {
+        id: 68,
+        meaning: "unknown (density?)",
+        confidence: Confidence::Guess,
+        fields: &[field("unknown0", 1, &[5])],
+    },
+    Command {
+        id: 71,
+        meaning: "unknown",
+        confidence: Confidence::Guess,
+        fields: &[],
+    },
+    Command {
+        id: 76,
+        meaning: "tape length in px (raster rows + margin)",
+        confidence: Confidence::Confirmed,
+        fields: &[field("tape_len", 4, &[])],
+    },
+    Command {
+        id: 84,
+        meaning: "unknown",
+        confidence: Confidence::Guess,
+        fields: &[field("unknown0", 2, &[42])],
+    },
+    Command {
+        id: 72,
+        meaning: "unknown",
+        confidence: Confidence::Guess,
+        fields: &[field("unknown0", 1, &[5])],
+    },
+    Command {
+        id: 115,
+        meaning: "unknown",
+        confidence: Confidence::Guess,
+        fields: &[field("unknown0", 1, &[0])],
+    },
+];
+
+pub fn lookup(id: u8) -> Option<&'static Command> {
+    COMMANDS.iter().find(|c| c.id == id)
+}
+
+/// A description of an ESC { command (the payload without the checksum)
+pub struct Annotation {
+    pub text: String,
+    /// Whether the command or its parameters differ from what has been seen
+    pub unseen: bool,
+}
+
+pub fn annotate(cmd: &[u8]) -> Annotation {
+    let (id, params) = match cmd {
+        [id, params @ ..] => (*id, params),
+        [] => {
+            return Annotation {
+                text: "UNSEEN: empty command".to_string(),
+                unseen: true,
+            }
+        }
+    };
+    let Some(command) = lookup(id) else {
+        return Annotation {
+            text: format!("UNSEEN: unknown command {id} ({:?})", id as char),
+            unseen: true,
+        };
+    };
+    let mut text = format!(
+        "{id} ({:?}): {} [{}]",
+        id as char, command.meaning, command.confidence
+    );
+    if params.len() != command.len() {
+        text += &format!(
+            " UNSEEN: {} bytes of parameters (expected {})",
+            params.len(),
+            command.len()
+        );
+        return Annotation { text, unseen: true };
+    }
+    let mut unseen = false;
+    let mut ofs = 0;
+    for f in command.fields {
+        let value = params[ofs..ofs + f.size]
+            .iter()
+            .rev()
+            .fold(0u32, |v, b| v << 8 | *b as u32);
+        ofs += f.size;
+        text += &format!(" {} = {value}", f.name);
+        if !f.seen.is_empty() && !f.seen.contains(&value) {
+            text += &format!(" (UNSEEN, expected {:?})", f.seen);
+            unseen = true;
+        }
+    }
+    Annotation { text, unseen }
+}
+
+#[test]
+fn annotate_commands() {
+    // the commands sent by gen_tcp_data are all known
+    let td = crate::display::TapeDisplay::new(10, 8);
+    let data = crate::print::gen_tcp_data(&td, &crate::Tape::W12.geometry()).unwrap();
+    let job = crate::analyzer::decode_tcp_data(&data).unwrap();
+    assert_eq!(job.commands.len(), 10);
+    for cmd in &job.commands {
+        let annotation = annotate(cmd);
+        assert!(!annotation.unseen, "{}", annotation.text);
+    }
+    assert_eq!(
+        annotate(&[76, 14, 0, 0, 0]).text,
+        "76 ('L'): tape length in px (raster rows + margin) [confirmed] tape_len = 14"
+    );
+    assert_eq!(
+        annotate(&[84, 43, 1]).text,
+        "84 ('T'): unknown [guess] unknown0 = 299 (UNSEEN, expected [42])"
+    );
+    assert!(annotate(&[68]).unseen);
+    assert!(annotate(&[1, 2]).unseen);
+    assert!(annotate(&[]).unseen);
+}
@@ -0,0 +1,386 @@
+use crate::display::TapeDisplay;
+use crate::reed_solomon::Galois;
+use anyhow::anyhow;
+use anyhow::Result;
+
+/// A symbol size of Data Matrix ECC 200 (ISO/IEC 16022, Table 7)
+struct SymbolSize {
+    rows: usize,
+    cols: usize,
+    /// Size of a data region, without the finder pattern around it
+    region_rows: usize,
+    region_cols: usize,
+    data_words: usize,
+    ecc_words: usize,
+    /// Number of interleaved Reed-Solomon blocks
+    blocks: usize,
+}
+const fn size(
+    rows: usize,
+    cols: usize,
+    region_rows: usize,
+    region_cols: usize,
+    data_words: usize,
+    ecc_words: usize,
+    blocks: usize,
+) -> SymbolSize {
+    SymbolSize {
+        rows,
+        cols,
+        region_rows,
+        region_cols,
+        data_words,
+        ecc_words,
+        blocks,
+    }
+}
+/// Square symbols up to 64x64, which is already too large for the widest tape
+/// to be scanned reliably
+const SQUARE_SIZES: [SymbolSize; 16] = [
+    size(10, 10, 8, 8, 3, 5, 1),
+    size(12, 12, 10, 10, 5, 7, 1),
+    size(14, 14, 12, 12, 8, 10, 1),
+    size(16, 16, 14, 14, 12, 12, 1),
+    size(18, 18, 16, 16, 18, 14, 1),
+    size(20, 20, 18, 18, 22, 18, 1),
+    size(22, 22, 20, 20, 30, 20, 1),
+    size(24, 24, 22, 22, 36, 24, 1),
+    size(26, 26, 24, 24, 44, 28, 1),
+    size(32, 32, 14, 14, 62, 36, 1),
+    size(36, 36, 16, 16, 86, 42, 1),
+    size(40, 40, 18, 18, 114, 48, 1),
+    size(44, 44, 20, 20, 144, 56, 1),
+    size(48, 48, 22, 22, 174, 68, 1),
+    size(52, 52, 24, 24, 204, 84, 2),
+    size(64, 64, 14, 14, 280, 112, 2),
+];
+const RECT_SIZES: [SymbolSize; 6] = [
+    size(8, 18, 6, 16, 5, 7, 1),
+    size(8, 32, 6, 14, 10, 11, 1),
+    size(12, 26, 10, 24, 16, 14, 1),
+    size(12, 36, 10, 16, 22, 18, 1),
+    size(16, 36, 14, 16, 32, 24, 1),
+    size(16, 48, 14, 22, 49, 28, 1),
+];
+
+const PAD: u8 = 129;
+const UPPER_SHIFT: u8 = 235;
+
+/// Encodes the bytes in ASCII encodation, with pairs of digits packed
+fn encode_ascii(data: &[u8]) -> Vec<u8> {
+    let mut words = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let c = data[i];
+        match data.get(i + 1) {
+            Some(d) if c.is_ascii_digit() && d.is_ascii_digit() => {
+                words.push(130 + (c - b'0') * 10 + (d - b'0'));
+                i += 2;
+                continue;
+            }
+            _ => {}
+        }
+        if c < 128 {
+            words.push(c + 1);
+        } else {
+            words.push(UPPER_SHIFT);
+            words.push(c - 127);
+        }
+        i += 1;
+    }
+    words
+}
+
+/// Fills the unused codewords with pads randomized by their position
+fn pad(words: &mut Vec<u8>, len: usize) {
+    if words.len() < len {
+        words.push(PAD);
+    }
+    while words.len() < len {
+        let r = (149 * (words.len() + 1)) % 253 + 1;
+        let v = PAD as usize + r;
+        words.push(if v > 254 { v - 254 } else { v } as u8);
+    }
+}
+
+/// Which bit of which codeword is at each module of the mapping matrix, by
+/// the algorithm in ISO/IEC 16022 Annex F. None is for the fixed pattern at
+/// the bottom right corner, which is left over in some sizes.
+fn placement(nrow: usize, ncol: usize) -> Vec<Option<(usize, u8)>> {
+    struct Grid {
+        nrow: isize,
+        ncol: isize,
+        cells: Vec<Option<(usize, u8)>>,
+    }
+    impl Grid {
+        fn is_unset(&self, row: isize, col: isize) -> bool {
+            (0..self.nrow).contains(&row)
+                && (0..self.ncol).contains(&col)
+                && self.cells[(row * self.ncol + col) as usize].is_none()
+        }
+        fn module(&mut self, mut row: isize, mut col: isize, word: usize, bit: u8) {
+            if row < 0 {
+                row += self.nrow;
+                col += 4 - ((self.nrow + 4) % 8);
+            }
+            if col < 0 {
+                col += self.ncol;
+                row += 4 - ((self.ncol + 4) % 8);
+            }
+            self.cells[(row * self.ncol + col) as usize] = Some((word, 0x80 >> (bit - 1)));
+        }
+        /// Places the bits of a codeword in the usual shape
+        fn utah(&mut self, row: isize, col: isize, word: usize) {
+            for (bit, (dr, dc)) in [
+                (-2, -2),
+                (-2, -1),
+                (-1, -2),
+                (-1, -1),
+                (-1, 0),
+                (0, -2),
+                (0, -1),
+                (0, 0),
+            ]
+            .into_iter()
+            .enumerate()
+            {
+                self.module(row + dr, col + dc, word, bit as u8 + 1);
+            }
+        }
+        /// Places the bits of a codeword wrapped around a corner
+        fn corner(&mut self, positions: [(isize, isize); 8], word: usize) {
+            for (bit, (row, col)) in positions.into_iter().enumerate() {
+                self.module(row, col, word, bit as u8 + 1);
+            }
+        }
+    }
+    let (nr, nc) = (nrow as isize, ncol as isize);
+    let mut g = Grid {
+        nrow: nr,
+        ncol: nc,
+        cells: vec![None; nrow * ncol],
+    };
+    let mut word = 0;
+    let (mut row, mut col) = (4, 0);
+    loop {
+        if row == nr && col == 0 {
+            #[rustfmt::skip]
+            g.corner([(nr - 1, 0), (nr - 1, 1), (nr - 1, 2), (0, nc - 2), (0, nc - 1), (1, nc - 1), (2, nc - 1), (3, nc - 1)], word);
+            word += 1;
+        }
+        if row == nr - 2 && col == 0 && nc % 4 != 0 {
+            #[rustfmt::skip]
+            g.corner([(nr - 3, 0), (nr - 2, 0), (nr - 1, 0), (0, nc - 4), (0, nc - 3), (0, nc - 2), (0, nc - 1), (1, nc - 1)], word);
+            word += 1;
+        }
+        if row == nr - 2 && col == 0 && nc % 8 == 4 {
+            #[rustfmt::skip]
+            g.corner([(nr - 3, 0), (nr - 2, 0), (nr - 1, 0), (0, nc - 2), (0, nc - 1), (1, nc - 1), (2, nc - 1), (3, nc - 1)], word);
+            word += 1;
+        }
+        if row == nr + 4 && col == 2 && nc % 8 == 0 {
+            #[rustfmt::skip]
+            g.corner([(nr - 1, 0), (nr - 1, nc - 1), (0, nc - 3), (0, nc - 2), (0, nc - 1), (1, nc - 3), (1, nc - 2), (1, nc - 1)], word);
+            word += 1;
+        }
+        // sweep upward diagonally
+        loop {
+            if g.is_unset(row, col) {
+                g.utah(row, col, word);
+                word += 1;
+            }
+            row -= 2;
+            col += 2;
+            if row < 0 || col >= nc {
+                break;
+            }
+        }
+        row += 1;
+        col += 3;
+        // and then downward
+        loop {
+            if g.is_unset(row, col) {
+                g.utah(row, col, word);
+                word += 1;
+            }
+            row += 2;
+            col -= 2;
+            if row >= nr || col < 0 {
+                break;
+            }
+        }
+        row += 3;
+        col += 1;
+        if row >= nr && col >= nc {
+            break;
+        }
+    }
+    g.cells
+}
+
+impl SymbolSize {
+    fn regions(&self) -> (usize, usize) {
+        (
+            self.rows / (self.region_rows + 2),
+            self.cols / (self.region_cols + 2),
+        )
+    }
+    /// Position of a module of the mapping matrix in the symbol
+    fn symbol_position(&self, row: usize, col: usize) -> (usize, usize) {
+        let (rr, rc) = (self.region_rows, self.region_cols);
+        (
+            row / rr * (rr + 2) + 1 + row % rr,
+            col / rc * (rc + 2) + 1 + col % rc,
+        )
+    }
+    fn mapping_size(&self) -> (usize, usize) {
+        let (v, h) = self.regions();
+        (v * self.region_rows, h * self.region_cols)
+    }
+    /// Appends the check words of the interleaved blocks
+    fn add_check_words(&self, words: &mut Vec<u8>) {
+        let gf = Galois::new(8, 0x12D);
+        let n = self.ecc_words / self.blocks;
+        for b in 0..self.blocks {
+            let block: Vec<u16> = words[b..self.data_words]
+                .iter()
+                .step_by(self.blocks)
+                .map(|w| *w as u16)
+                .collect();
+            let ecc = gf.check_words(&block, n);
+            words.resize(self.data_words + self.ecc_words, 0);
+            for (i, w) in ecc.iter().enumerate() {
+                words[self.data_words + i * self.blocks + b] = *w as u8;
+            }
+        }
+    }
+    fn draw(&self, words: &[u8]) -> TapeDisplay {
+        let mut td = TapeDisplay::new(self.cols, self.rows);
+        // finder patterns: solid on the left and bottom, dotted on the top and right
+        let (rr, rc) = (self.region_rows + 2, self.region_cols + 2);
+        for y in 0..self.rows {
+            for x in 0..self.cols {
+                let (ry, rx) = (y % rr, x % rc);
+                if rx == 0 || ry == rr - 1 {
+                    td.set_pixel(x, y, true);
+                } else if ry == 0 {
+                    td.set_pixel(x, y, rx % 2 == 0);
+                } else if rx == rc - 1 {
+                    td.set_pixel(x, y, ry % 2 == 1);
+                }
+            }
+        }
+        let (nrow, ncol) = self.mapping_size();
+        for (i, cell) in placement(nrow, ncol).iter().enumerate() {
+            let (row, col) = (i / ncol, i % ncol);
+            let dark = match cell {
+                Some((word, mask)) => words[*word] & mask != 0,
+                None => (row + col) % 2 == (nrow + ncol) % 2,
+            };
+            let (y, x) = self.symbol_position(row, col);
+            td.set_pixel(x, y, dark);
+        }
+        td
+    }
+}
+
+/// Encodes the text as a Data Matrix ECC 200 symbol in the smallest size.
+/// Returns the modules as pixels, with rows along the width of the tape.
+pub fn encode(text: &str, rectangular: bool) -> Result<TapeDisplay> {
+    let mut words = encode_ascii(text.as_bytes());
+    let sizes: &[SymbolSize] = if rectangular {
+        &RECT_SIZES
+    } else {
+        &SQUARE_SIZES
+    };
+    let size = sizes
+        .iter()
+        .find(|s| s.data_words >= words.len())
+        .ok_or_else(|| {
+            anyhow!(
+                "The text ({} codewords) does not fit a {} Data Matrix (up to {} codewords)",
+                words.len(),
+                if rectangular { "rectangular" } else { "square" },
+                sizes.last().unwrap().data_words,
+            )
+        })?;
+    pad(&mut words, size.data_words);
+    size.add_check_words(&mut words);
+    Ok(size.draw(&words))
+}
+
+#[test]
+fn datamatrix() {
+    // the example in ISO/IEC 16022
+    let mut words = encode_ascii(b"123456");
+    assert_eq!(words, [142, 164, 186]);
+    SQUARE_SIZES[0].add_check_words(&mut words);
+    assert_eq!(words, [142, 164, 186, 114, 25, 5, 88, 102]);
+
+    // each size maps all the codewords to its data regions
+    for s in SQUARE_SIZES.iter().chain(&RECT_SIZES) {
+        let (nrow, ncol) = s.mapping_size();
+        let cells = placement(nrow, ncol);
+        let mut bits = vec![0u8; s.data_words + s.ecc_words];
+        for (word, mask) in cells.iter().flatten() {
+            assert_eq!(bits[*word] & mask, 0);
+            bits[*word] |= mask;
+        }
+        assert!(bits.iter().all(|b| *b == 0xff), "{}x{}", s.rows, s.cols);
+        assert!(cells.iter().filter(|c| c.is_none()).count() <= 4);
+    }
+
+    // read the codewords back from the modules
+    let gf = Galois::new(8, 0x12D);
+    for (text, rectangular) in [
+        ("Hello, world!", false),
+        ("0123456789", true),
+        ("SRV-0001", true),
+        (&"Data Matrix ".repeat(20) as &str, false),
+    ] {
+        let td = encode(text, rectangular).unwrap();
+        let s = SQUARE_SIZES
+            .iter()
+            .chain(&RECT_SIZES)
+            .find(|s| (s.cols, s.rows) == (td.width, td.height))
+            .unwrap();
+        // the finder pattern
+        for y in 0..s.rows {
+            assert!(td.get_pixel(0, y));
+            assert_eq!(td.get_pixel(s.cols - 1, y), y % 2 == 1);
+        }
+        for x in 0..s.cols {
+            assert!(td.get_pixel(x, s.rows - 1));
+            assert_eq!(td.get_pixel(x, 0), x % 2 == 0);
+        }
+        let (nrow, ncol) = s.mapping_size();
+        let mut words = vec![0u8; s.data_words + s.ecc_words];
+        for (i, cell) in placement(nrow, ncol).iter().enumerate() {
+            let (y, x) = s.symbol_position(i / ncol, i % ncol);
+            if let Some((word, mask)) = cell {
+                if td.get_pixel(x, y) {
+                    words[*word] |= mask;
+                }
+            }
+        }
+        for b in 0..s.blocks {
+            let block: Vec<u16> = words[b..]
+                .iter()
+                .step_by(s.blocks)
+                .map(|w| *w as u16)
+                .collect();
+            assert!(gf.is_valid(&block, s.ecc_words / s.blocks));
+        }
+        let data = &words[..s.data_words];
+        let end = data.iter().position(|w| *w == PAD).unwrap_or(data.len());
+        let mut decoded = Vec::new();
+        for w in &data[..end] {
+            match *w {
+                1..=128 => decoded.push(w - 1),
+                130..=229 => decoded.extend(format!("{:02}", w - 130).bytes()),
+                _ => panic!("unexpected codeword {w}"),
+            }
+        }
+        assert_eq!(decoded, text.as_bytes());
+    }
+}
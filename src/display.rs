@@ -4,6 +4,7 @@ use embedded_graphics::prelude::OriginDimensions;
 use embedded_graphics::prelude::Size;
 use embedded_graphics::Pixel;
 
+#[derive(Clone)]
 pub struct TapeDisplay {
     pub framebuffer: Vec<Vec<bool>>,
     pub width: usize,
@@ -40,6 +41,34 @@ impl TapeDisplay {
         }
         new
     }
+    /// Resamples to the given size. Each new pixel is on if half or more of
+    /// the area it covers is on, so that thin lines survive downscaling
+    /// better than with the nearest neighbor.
+    pub fn resized(&self, width: usize, height: usize) -> Self {
+        let mut new = Self::new(width, height);
+        if self.width == 0 || self.height == 0 {
+            return new;
+        }
+        let src_range = |i: usize, new_len: usize, len: usize| {
+            let begin = i * len / new_len;
+            let end = std::cmp::max((i + 1) * len / new_len, begin + 1);
+            begin..std::cmp::min(end, len)
+        };
+        for y in 0..height {
+            let ys = src_range(y, height, self.height);
+            for x in 0..width {
+                let xs = src_range(x, width, self.width);
+                let total = ys.len() * xs.len();
+                let on = ys
+                    .clone()
+                    .flat_map(|sy| xs.clone().map(move |sx| (sx, sy)))
+                    .filter(|(sx, sy)| self.get_pixel(*sx, *sy))
+                    .count();
+                new.set_pixel(x, y, on * 2 >= total);
+            }
+        }
+        new
+    }
     pub fn overlay_or(&mut self, td: &Self, px: usize, py: usize) {
         for y in py..self.height {
             for x in px..self.width {
@@ -90,6 +119,7 @@ impl OriginDimensions for TapeDisplay {
 }
 
 #[test]
+#[allow(clippy::bool_assert_comparison)]
 fn transforms() {
     // 2x2
     let mut td = TapeDisplay::new(2, 2);
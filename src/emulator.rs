@@ -0,0 +1,157 @@
+use crate::protocol::Codec;
+use crate::protocol::PacketHeader;
+use crate::Tape;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use argh::FromArgs;
+use std::fs;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::net::UdpSocket;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+
+/// A minimal emulator of the printer, to try and test the tools without the hardware.
+/// It answers the UDP commands as an idle printer with the given tape, and
+/// records the data received on each TCP connection (i.e. each print session).
+pub struct Emulator {
+    addr: SocketAddr,
+    sessions: Arc<Mutex<Vec<Vec<u8>>>>,
+    /// The cmd of each UDP request, in the order they are received
+    commands: Arc<Mutex<Vec<u32>>>,
+    /// Close the TCP connections without reading the data
+    fail_data: Arc<AtomicBool>,
+}
+impl Emulator {
+    /// Starts the emulator in background threads. The port in addr can be 0,
+    /// in which case the actual address can be obtained with addr().
+    pub fn start(addr: &str, tape: Tape, dump_dir: Option<PathBuf>) -> Result<Self> {
+        let listener = TcpListener::bind(addr).context(anyhow!("Failed to listen on {addr}"))?;
+        let addr = listener.local_addr()?;
+        // The printer uses the same port number for both UDP and TCP
+        let socket = UdpSocket::bind(addr).context(anyhow!("Failed to bind {addr}/udp"))?;
+        let commands = Arc::new(Mutex::new(Vec::new()));
+        {
+            let commands = commands.clone();
+            thread::spawn(move || loop {
+                if let Err(e) = respond_to_command(&socket, tape, &commands) {
+                    eprintln!("emulator: {e:#}");
+                }
+            });
+        }
+        let sessions = Arc::new(Mutex::new(Vec::new()));
+        let fail_data = Arc::new(AtomicBool::new(false));
+        {
+            let sessions = sessions.clone();
+            let fail_data = fail_data.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if fail_data.load(Ordering::Relaxed) {
+                        println!("emulator: closing the connection without reading");
+                        continue;
+                    }
+                    let sessions = sessions.clone();
+                    let dump_dir = dump_dir.clone();
+                    thread::spawn(move || {
+                        let mut data = Vec::new();
+                        if let Err(e) = stream.and_then(|mut s| s.read_to_end(&mut data)) {
+                            eprintln!("emulator: {e}");
+                        }
+                        let mut sessions = sessions.lock().unwrap();
+                        sessions.push(data);
+                        let data = sessions.last().unwrap();
+                        println!("emulator: received {} bytes", data.len());
+                        if let Some(dir) = dump_dir {
+                            let path = dir.join(format!("session{}.bin", sessions.len()));
+                            if let Err(e) = fs::write(&path, data) {
+                                eprintln!("emulator: failed to write {path:?}: {e}");
+                            }
+                        }
+                    });
+                }
+            });
+        }
+        Ok(Self {
+            addr,
+            sessions,
+            commands,
+            fail_data,
+        })
+    }
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+    /// Returns the data received in each print session that is finished
+    pub fn sessions(&self) -> Vec<Vec<u8>> {
+        self.sessions.lock().unwrap().clone()
+    }
+    /// Returns the cmd of the UDP requests received so far
+    pub fn commands(&self) -> Vec<u32> {
+        self.commands.lock().unwrap().clone()
+    }
+    /// If fail is true, the TCP connections are closed without reading the
+    /// data, so that sending a label fails as if the printer is gone.
+    pub fn fail_data(&self, fail: bool) {
+        self.fail_data.store(fail, Ordering::Relaxed);
+    }
+}
+
+fn respond_to_command(socket: &UdpSocket, tape: Tape, commands: &Mutex<Vec<u32>>) -> Result<()> {
+    let mut buf = [0; 128];
+    let (len, from) = socket.recv_from(&mut buf)?;
+    let (req, _) = PacketHeader::parse_request(&buf[..len]).context(anyhow!("from {from}"))?;
+    let cmd = req.cmd();
+    commands.lock().unwrap().push(cmd);
+    let data: Vec<u8> = match cmd {
+        // status of an idle printer with a standard tape
+        1 => {
+            let mut data = vec![0u8; 20];
+            data[0x00] = 20;
+            data[0x03] = tape.index();
+            data[0x08] = 0x40;
+            data
+        }
+        2 => vec![2, 0, 0],
+        3 => vec![3, 0, 0],
+        0x0100 => vec![0x10],
+        _ => Vec::new(),
+    };
+    let mut res = PacketHeader::new_response(cmd, data.len() as u32).encode();
+    res.extend(data);
+    socket.send_to(&res, from)?;
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Emulate a printer on this machine
+#[argh(subcommand, name = "emulate")]
+pub struct EmulateArgs {
+    /// address to listen on (default: 127.0.0.1:9100)
+    #[argh(option)]
+    listen: Option<String>,
+    /// tape width in mm (default: 12)
+    #[argh(option)]
+    width: Option<usize>,
+    /// a directory to write the received data (session1.bin, session2.bin, ...)
+    #[argh(option)]
+    dump_dir: Option<String>,
+}
+
+pub fn do_emulate(args: &EmulateArgs) -> Result<()> {
+    let tape = Tape::from_mm(args.width.unwrap_or(12))?;
+    let emulator = Emulator::start(
+        args.listen.as_deref().unwrap_or("127.0.0.1:9100"),
+        tape,
+        args.dump_dir.as_ref().map(PathBuf::from),
+    )?;
+    println!("Emulating a printer with {tape:?} at {}", emulator.addr());
+    loop {
+        thread::park();
+    }
+}
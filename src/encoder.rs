@@ -0,0 +1,267 @@
+use crate::display::TapeDisplay;
+use crate::TapeGeometry;
+use anyhow::anyhow;
+use anyhow::Result;
+use std::io::Write;
+use std::num::Wrapping;
+
+/// Added to the tape length sent to the printer, on top of the number of raster rows.
+/// The label length itself should be controlled with LengthOptions.
+const TAPE_LEN_SAFE_MARGIN_PX: u32 = 4;
+
+const JOB_END: [u8; 7] = [0x0c, 27, 123, 3, 64, 64, 125];
+
+/// A label that can be encoded one raster row at a time, so that the whole
+/// bitmap does not have to be in memory. A row is the line of pixels across
+/// the tape at x, in the coordinates of TapeDisplay.
+pub trait RowSource {
+    /// The length of the label in px
+    fn width(&self) -> usize;
+    /// The number of pixels across the tape
+    fn height(&self) -> usize;
+    /// Fills row (height() pixels) with the pixels at x
+    fn fill_row(&mut self, x: usize, row: &mut [bool]);
+}
+impl RowSource for &TapeDisplay {
+    fn width(&self) -> usize {
+        self.width
+    }
+    fn height(&self) -> usize {
+        self.height
+    }
+    fn fill_row(&mut self, x: usize, row: &mut [bool]) {
+        for (y, p) in row.iter_mut().enumerate() {
+            *p = self.get_pixel(x, y);
+        }
+    }
+}
+
+impl<S: RowSource + ?Sized> RowSource for &mut S {
+    fn width(&self) -> usize {
+        (**self).width()
+    }
+    fn height(&self) -> usize {
+        (**self).height()
+    }
+    fn fill_row(&mut self, x: usize, row: &mut [bool]) {
+        (**self).fill_row(x, row)
+    }
+}
+
+/// Renders all the rows into a TapeDisplay, e.g. for a preview
+pub fn render_rows<S: RowSource>(mut source: S) -> TapeDisplay {
+    let mut td = TapeDisplay::new(source.width(), source.height());
+    let mut row = vec![false; source.height()];
+    for x in 0..td.width {
+        source.fill_row(x, &mut row);
+        for (y, p) in row.iter().enumerate() {
+            td.set_pixel(x, y, *p);
+        }
+    }
+    td
+}
+
+/// Encodes a print job as an iterator of chunks: the header, each raster
+/// row, and the end of the job. The rows are pulled from the source only
+/// when the chunk is requested, so the job can be sent while it is generated.
+pub struct JobEncoder<S: RowSource> {
+    source: S,
+    head_offset_dots: usize,
+    /// 0 is the header, 1..=width are the rows and width + 1 is the end
+    next: usize,
+    row: Vec<bool>,
+}
+impl<S: RowSource> JobEncoder<S> {
+    pub fn new(source: S, geometry: &TapeGeometry) -> Result<Self> {
+        if source.height() > geometry.printable_px() {
+            return Err(anyhow!(
+                "The label ({} px) is wider than the printable area of the tape ({} px)",
+                source.height(),
+                geometry.printable_px()
+            ));
+        }
+        let row = vec![false; source.height()];
+        Ok(Self {
+            source,
+            head_offset_dots: geometry.head_offset_dots,
+            next: 0,
+            row,
+        })
+    }
+    /// Writes the rest of the job to w chunk by chunk. With a buffered
+    /// writer on a socket, the encoding waits while the printer is busy.
+    pub fn write_to<W: Write>(self, w: &mut W) -> Result<()> {
+        for chunk in self {
+            w.write_all(&chunk)?;
+        }
+        w.flush()?;
+        Ok(())
+    }
+}
+impl<S: RowSource> Iterator for JobEncoder<S> {
+    type Item = Vec<u8>;
+    fn next(&mut self) -> Option<Vec<u8>> {
+        let width = self.source.width();
+        let chunk = match self.next {
+            0 => job_header(width),
+            i if i <= width => {
+                // The rows are sent from the end of the label
+                self.source.fill_row(width - i, &mut self.row);
+                encode_row(&self.row, self.head_offset_dots)
+            }
+            i if i == width + 1 => JOB_END.to_vec(),
+            _ => return None,
+        };
+        self.next += 1;
+        Some(chunk)
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let n = (self.source.width() + 2).saturating_sub(self.next);
+        (n, Some(n))
+    }
+}
+
+fn job_header(width: usize) -> Vec<u8> {
+    let mut data: Vec<u8> = Vec::new();
+    data.append(&mut vec![27, 123, 3, 64, 64, 125]);
+    data.append(&mut vec![27, 123, 7, 123, 0, 0, 83, 84, 34, 125]);
+    data.append(&mut vec![27, 123, 7, 67, 2, 2, 1, 1, 73, 125]); // half-cut?
+    data.append(&mut vec![27, 123, 4, 68, 5, 73, 125]);
+    data.append(&mut vec![27, 123, 3, 71, 71, 125]);
+
+    let mut tape_len_bytes = (width as u32 + TAPE_LEN_SAFE_MARGIN_PX)
+        .to_le_bytes()
+        .to_vec();
+    let mut cmd_bytes = vec![76];
+    cmd_bytes.append(&mut tape_len_bytes);
+    let csum = cmd_bytes
+        .iter()
+        .map(|v| Wrapping(*v))
+        .sum::<Wrapping<u8>>()
+        .0;
+    cmd_bytes.push(csum);
+    cmd_bytes.push(0x7d);
+    data.append(&mut vec![0x1b, 0x7b, cmd_bytes.len() as u8]);
+    data.append(&mut cmd_bytes);
+
+    data.append(&mut vec![27, 123, 5, 84, 42, 0, 126, 125]);
+    data.append(&mut vec![27, 123, 4, 72, 5, 77, 125]);
+    data.append(&mut vec![27, 123, 4, 115, 0, 115, 125]);
+    data
+}
+
+/// Encodes a raster row. The printable area starts at head_offset_dots in the row.
+fn encode_row(row: &[bool], head_offset_dots: usize) -> Vec<u8> {
+    let ofs = head_offset_dots;
+    let row_bits = ofs + row.len();
+    let row_bytes = row_bits.div_ceil(8);
+    let mut data = vec![0x1b, 0x2e, 0, 0, 0, 1];
+    data.extend((row_bits as u16).to_le_bytes());
+    for xb in 0..row_bytes {
+        let mut chunk = 0x00;
+        for dx in 0..8 {
+            let x = xb * 8 + (7 - dx);
+            if x >= ofs && row.get(x - ofs) == Some(&true) {
+                chunk |= 1 << dx
+            }
+        }
+        data.push(chunk);
+    }
+    data
+}
+
+#[test]
+fn streaming_matches_bitmap() {
+    use crate::Tape;
+
+    /// A pattern computed on the fly, without a bitmap
+    struct Diagonal {
+        width: usize,
+        height: usize,
+    }
+    impl RowSource for Diagonal {
+        fn width(&self) -> usize {
+            self.width
+        }
+        fn height(&self) -> usize {
+            self.height
+        }
+        fn fill_row(&mut self, x: usize, row: &mut [bool]) {
+            for (y, p) in row.iter_mut().enumerate() {
+                *p = (x + 2 * y).is_multiple_of(7);
+            }
+        }
+    }
+    let geometry = Tape::W12.geometry();
+    let (width, height) = (100, geometry.printable_px());
+    let mut td = TapeDisplay::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            td.set_pixel(x, y, (x + 2 * y).is_multiple_of(7));
+        }
+    }
+    let from_td: Vec<Vec<u8>> = JobEncoder::new(&td, &geometry).unwrap().collect();
+    assert_eq!(from_td.len(), width + 2);
+    let mut streamed = Vec::new();
+    JobEncoder::new(Diagonal { width, height }, &geometry)
+        .unwrap()
+        .write_to(&mut streamed)
+        .unwrap();
+    assert_eq!(streamed, from_td.concat());
+    assert!(streamed.ends_with(&JOB_END));
+    // the printable area is shifted by the head offset in the rows
+    let shifted = TapeGeometry {
+        head_offset_dots: 16,
+        ..geometry
+    };
+    let data = crate::print::gen_tcp_data(&td, &shifted).unwrap();
+    let job = crate::analyzer::decode_tcp_data(&data).unwrap();
+    assert!(job.rows.iter().all(|(bits, _)| *bits == 16 + height));
+    assert!(job.to_display(16).unwrap().framebuffer == td.framebuffer);
+    assert!(job.to_display(0).unwrap().framebuffer != td.framebuffer);
+    // the label should fit in the printable area
+    let td = TapeDisplay::new(width, height + 1);
+    assert!(JobEncoder::new(&td, &geometry).is_err());
+}
+
+#[cfg(test)]
+proptest::proptest! {
+    #[test]
+    fn decode_round_trip(
+        tape in proptest::sample::select(vec![4, 6, 9, 12, 18, 24, 36]),
+        width in 1usize..200,
+        height_ratio in 0.0f64..=1.0,
+        seed: u64,
+    ) {
+        use crate::analyzer::decode_tcp_data;
+        use crate::Tape;
+
+        let geometry = Tape::from_mm(tape).unwrap().geometry();
+        // any height up to the printable area, not only multiples of 8
+        let height = 1 + ((geometry.printable_px() - 1) as f64 * height_ratio) as usize;
+        let mut td = TapeDisplay::new(width, height);
+        let mut state = seed | 1;
+        for y in 0..height {
+            for x in 0..width {
+                // xorshift, so that the bitmap is cheap to generate
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                td.set_pixel(x, y, state & 1 == 1);
+            }
+        }
+        let data = crate::print::gen_tcp_data(&td, &geometry).unwrap();
+        let job = decode_tcp_data(&data).unwrap();
+        proptest::prop_assert!(job.ended);
+        proptest::prop_assert!(data.ends_with(&JOB_END));
+        proptest::prop_assert_eq!(job.tape_len, Some(width as u32 + TAPE_LEN_SAFE_MARGIN_PX));
+        proptest::prop_assert_eq!(job.rows.len(), width);
+        for (bits, row) in &job.rows {
+            proptest::prop_assert_eq!(*bits, geometry.head_offset_dots + height);
+            proptest::prop_assert_eq!(row.len(), bits.div_ceil(8));
+        }
+        let decoded = job.to_display(geometry.head_offset_dots).unwrap();
+        proptest::prop_assert_eq!((decoded.width, decoded.height), (width, height));
+        proptest::prop_assert!(decoded.framebuffer == td.framebuffer);
+    }
+}
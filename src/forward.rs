@@ -0,0 +1,143 @@
+use crate::encoder::JobEncoder;
+use crate::print::detect_geometry;
+use crate::print::PrintSession;
+use crate::raster::decode_pages;
+use crate::raster::fit_to_tape;
+use crate::raster::MAX_JOB_BYTES;
+use crate::PrinterStatus;
+use crate::TapeKind;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use argh::FromArgs;
+use std::io::Read;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::time::Duration;
+
+/// A client which sends nothing for this long is dropped, so that it does
+/// not block the jobs of the other clients.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Receives a job on a raw socket (like the "socket://" backend of CUPS) and prints it.
+fn forward_job(stream: &mut TcpStream, args: &ForwardArgs) -> Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut data = Vec::new();
+    stream.take(MAX_JOB_BYTES + 1).read_to_end(&mut data)?;
+    if data.len() as u64 > MAX_JOB_BYTES {
+        return Err(anyhow!("The job is larger than {MAX_JOB_BYTES} bytes"));
+    }
+    println!("Received {} bytes", data.len());
+    let pages = decode_pages(&data)?;
+    let geometry = detect_geometry(Some(&args.printer), args.width, args.tape_kind)?;
+    let labels: Vec<_> = pages
+        .iter()
+        .map(|page| fit_to_tape(page, geometry.printable_px(), args.trim))
+        .collect();
+    let mut session = PrintSession::open(&args.printer)?;
+    let print_pages = |session: &mut PrintSession| -> Result<()> {
+        for (i, label) in labels.iter().enumerate() {
+            println!("Printing page {}/{}", i + 1, labels.len());
+            match session.print_stream(JobEncoder::new(label, &geometry)?)? {
+                PrinterStatus::SomeTape(..) | PrinterStatus::UnknownTape(..) => {}
+                status => return Err(anyhow!("Stopped at page {}: {status:?}", i + 1)),
+            }
+        }
+        Ok(())
+    };
+    match print_pages(&mut session) {
+        Ok(()) => session.close(),
+        Err(e) => Err(session.abort(e)),
+    }
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Accept PNG or PWG raster jobs on a raw socket and forward them to the printer
+#[argh(subcommand, name = "forward")]
+pub struct ForwardArgs {
+    /// an IPv4 address for the printer (or host:port, e.g. for an emulator)
+    #[argh(option)]
+    printer: String,
+    /// address to listen on for the jobs (default: 0.0.0.0:9100)
+    #[argh(option)]
+    listen: Option<String>,
+    /// tape width in mm (default: auto)
+    #[argh(option)]
+    width: Option<usize>,
+    /// kind of the tape: standard, clear, iron-on, magnet or tube (default: auto)
+    #[argh(option)]
+    tape_kind: Option<TapeKind>,
+    /// remove blank margins of the pages before fitting them to the tape
+    #[argh(switch)]
+    trim: bool,
+}
+
+pub fn do_forward(args: &ForwardArgs) -> Result<()> {
+    let listen = args.listen.as_deref().unwrap_or("0.0.0.0:9100");
+    let listener = TcpListener::bind(listen).context(anyhow!("Failed to listen on {listen}"))?;
+    println!("Forwarding jobs on {listen} to {}", args.printer);
+    // Jobs are handled one by one, since the printer accepts only one session at a time
+    for stream in listener.incoming() {
+        let result = stream
+            .map_err(anyhow::Error::from)
+            .and_then(|mut stream| forward_job(&mut stream, args));
+        if let Err(e) = result {
+            eprintln!("Failed to forward a job: {e:#}");
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn forward_png_to_emulator() {
+    use crate::analyzer::decode_tcp_data;
+    use crate::emulator::Emulator;
+    use crate::Tape;
+    use std::io::Write;
+    use std::net::Shutdown;
+
+    let emulator = Emulator::start("127.0.0.1:0", Tape::W12, None).unwrap();
+    let args = ForwardArgs {
+        printer: emulator.addr().to_string(),
+        listen: None,
+        width: None,
+        tape_kind: None,
+        trim: false,
+    };
+    // a 64x16 page with the left half dark
+    let image =
+        image::GrayImage::from_fn(64, 16, |x, _| image::Luma([if x < 32 { 0 } else { 255 }]));
+    let mut png = Vec::new();
+    image::DynamicImage::ImageLuma8(image)
+        .write_to(&mut png, image::ImageFormat::Png)
+        .unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (mut accepted, _) = listener.accept().unwrap();
+    client.write_all(&png).unwrap();
+    client.shutdown(Shutdown::Write).unwrap();
+    forward_job(&mut accepted, &args).unwrap();
+
+    let geometry = Tape::W12.geometry();
+    let expected = fit_to_tape(
+        &decode_pages(&png).unwrap()[0],
+        geometry.printable_px(),
+        false,
+    );
+    // the session is recorded when the connection is closed
+    while emulator.sessions().is_empty() {
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    let sessions = emulator.sessions();
+    assert_eq!(sessions.len(), 1);
+    let printed = decode_tcp_data(&sessions[0])
+        .unwrap()
+        .to_display(geometry.head_offset_dots)
+        .unwrap();
+    assert_eq!(
+        (printed.width, printed.height),
+        (expected.width, expected.height)
+    );
+    assert!(printed.framebuffer == expected.framebuffer);
+}
@@ -0,0 +1,180 @@
+use crate::display::TapeDisplay;
+use crate::print::mm_to_px;
+use crate::print::px_to_mm;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use std::str::FromStr;
+
+/// A length along the tape, given as "50mm", "5cm", "2in" or just "50" (in mm)
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Mm(pub f32);
+impl Mm {
+    pub fn px(&self) -> usize {
+        mm_to_px(self.0) as usize
+    }
+}
+impl FromStr for Mm {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let (num, unit) = if let Some(num) = s.strip_suffix("mm") {
+            (num, 1.0)
+        } else if let Some(num) = s.strip_suffix("cm") {
+            (num, 10.0)
+        } else if let Some(num) = s.strip_suffix("in") {
+            (num, 25.4)
+        } else {
+            (s, 1.0)
+        };
+        let v: f32 = num
+            .trim()
+            .parse()
+            .context(anyhow!("Invalid length: {s} (e.g. 50mm)"))?;
+        if !v.is_finite() || v < 0.0 {
+            return Err(anyhow!("Invalid length: {s} (should be 0 or larger)"));
+        }
+        Ok(Mm(v * unit))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum Align {
+    Left,
+    #[default]
+    Center,
+    Right,
+}
+impl FromStr for Align {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "left" => Align::Left,
+            "center" => Align::Center,
+            "right" => Align::Right,
+            _ => return Err(anyhow!("Unknown alignment: {s} (left, center or right)")),
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct LengthOptions {
+    /// The exact length of the printed area. It is an error if the content
+    /// does not fit. The tape fed by the printer is TAPE_LEN_SAFE_MARGIN_PX
+    /// (4 px, about 0.3 mm) longer, see encoder.rs.
+    pub length: Option<Mm>,
+    /// The label will be extended to this length if the content is shorter.
+    /// It can not be combined with length.
+    pub min_length: Option<Mm>,
+    pub margin_left: Option<Mm>,
+    pub margin_right: Option<Mm>,
+    /// Where to put the content if the label is longer than the content
+    pub align: Align,
+}
+impl LengthOptions {
+    /// Returns a new td with the margins added and the length adjusted.
+    pub fn apply(&self, td: &TapeDisplay) -> Result<TapeDisplay> {
+        let (width, ofs_x) = self.layout(td.width)?;
+        let mut new = TapeDisplay::new(width, td.height);
+        new.overlay_or(td, ofs_x, 0);
+        Ok(new)
+    }
+    /// Returns the length of the label and the offset of the content in it,
+    /// for a content of the given length (all in px).
+    pub fn layout(&self, content_px: usize) -> Result<(usize, usize)> {
+        let margin_left = self.margin_left.map_or(0, |m| m.px());
+        let margin_right = self.margin_right.map_or(0, |m| m.px());
+        let content_width = margin_left + content_px + margin_right;
+        if self.length.is_some() && self.min_length.is_some() {
+            return Err(anyhow!(
+                "The label length and the minimum length can not be given together"
+            ));
+        }
+        let width = if let Some(length) = self.length {
+            if content_width > length.px() {
+                return Err(anyhow!(
+                    "The content ({:.1} mm including margins) does not fit in the label length ({:.1} mm)",
+                    px_to_mm(content_width),
+                    length.0
+                ));
+            }
+            length.px()
+        } else {
+            std::cmp::max(content_width, self.min_length.map_or(0, |m| m.px()))
+        };
+        let extra = width - content_width;
+        let ofs_x = margin_left
+            + match self.align {
+                Align::Left => 0,
+                Align::Center => extra / 2,
+                Align::Right => extra,
+            };
+        Ok((width, ofs_x))
+    }
+}
+
+#[test]
+fn length_options() {
+    assert_eq!("50mm".parse::<Mm>().unwrap(), Mm(50.0));
+    assert_eq!(" 5cm".parse::<Mm>().unwrap(), Mm(50.0));
+    assert_eq!("2in".parse::<Mm>().unwrap(), Mm(50.8));
+    assert_eq!("12.5".parse::<Mm>().unwrap(), Mm(12.5));
+    assert_eq!(Mm(25.4).px(), 360);
+    for s in ["", "mm", "-1mm", "inf", "NaN", "5 m"] {
+        assert!(s.parse::<Mm>().is_err(), "{s}");
+    }
+    assert_eq!("left".parse::<Align>().unwrap(), Align::Left);
+    assert_eq!("right".parse::<Align>().unwrap(), Align::Right);
+    assert!("top".parse::<Align>().is_err());
+
+    let mm = |v| Some(Mm(v));
+    // without options, the label is as long as the content
+    assert_eq!(LengthOptions::default().layout(100).unwrap(), (100, 0));
+    let opts = LengthOptions {
+        margin_left: mm(2.54),
+        margin_right: mm(5.08),
+        ..Default::default()
+    };
+    assert_eq!(opts.layout(100).unwrap(), (208, 36));
+    for (align, ofs_x) in [(Align::Left, 0), (Align::Center, 130), (Align::Right, 260)] {
+        let opts = LengthOptions {
+            length: mm(25.4),
+            align,
+            ..Default::default()
+        };
+        assert_eq!(opts.layout(100).unwrap(), (360, ofs_x));
+    }
+    let opts = LengthOptions {
+        min_length: mm(25.4),
+        align: Align::Right,
+        ..Default::default()
+    };
+    assert_eq!(opts.layout(100).unwrap(), (360, 260));
+    assert_eq!(opts.layout(400).unwrap(), (400, 0));
+    // the content does not fit
+    let opts = LengthOptions {
+        length: mm(25.4),
+        margin_left: mm(2.54),
+        ..Default::default()
+    };
+    assert!(opts.layout(325).is_err());
+    assert!(opts.layout(324).is_ok());
+    let opts = LengthOptions {
+        length: mm(25.4),
+        min_length: mm(10.0),
+        ..Default::default()
+    };
+    assert!(opts.layout(100).is_err());
+
+    let mut td = TapeDisplay::new(2, 3);
+    td.set_pixel(1, 2, true);
+    let opts = LengthOptions {
+        length: mm(25.4),
+        align: Align::Right,
+        ..Default::default()
+    };
+    let new = opts.apply(&td).unwrap();
+    assert_eq!((new.width, new.height), (360, 3));
+    assert!(new.get_pixel(359, 2));
+    assert_eq!(new.framebuffer.iter().flatten().filter(|p| **p).count(), 1);
+}
@@ -1,15 +1,33 @@
-#![feature(new_uninit)]
-#![feature(slice_take)]
-#![feature(exclusive_range_pattern)]
 pub mod analyzer;
+#[cfg(feature = "async")]
+pub mod async_client;
+pub mod aztec;
+pub mod banner;
+pub mod commands;
+pub mod datamatrix;
 pub mod display;
+pub mod emulator;
+pub mod encoder;
+pub mod forward;
+pub mod layout;
+pub mod payload;
+pub mod preview;
 pub mod print;
 pub mod protocol;
+pub mod proxy;
+pub mod qr;
+pub mod raster;
+pub mod reed_solomon;
+pub mod sequence;
+pub mod serve;
+pub mod svg;
+pub mod terminal;
 
 use crate::print::mm_to_px;
 use crate::protocol::PacketHeader;
 use anyhow::anyhow;
 use anyhow::Result;
+use std::str::FromStr;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Tape {
@@ -34,8 +52,45 @@ impl Tape {
             _ => return Err(anyhow!("Tape for {mm} mm is not defined")),
         })
     }
-    fn width_px(&self) -> i32 {
-        let w = match self {
+    pub fn width_mm(&self) -> usize {
+        match self {
+            Tape::W4 => 4,
+            Tape::W6 => 6,
+            Tape::W9 => 9,
+            Tape::W12 => 12,
+            Tape::W18 => 18,
+            Tape::W24 => 24,
+            Tape::W36 => 36,
+        }
+    }
+    /// Decodes the tape index in the status (byte 0x03)
+    pub fn from_index(index: u8) -> Option<Self> {
+        Some(match index {
+            0x01 => Tape::W6,
+            0x02 => Tape::W9,
+            0x03 => Tape::W12,
+            0x04 => Tape::W18,
+            0x05 => Tape::W24,
+            0x06 => Tape::W36,
+            0x0B => Tape::W4,
+            _ => return None,
+        })
+    }
+    /// The width index reported by the printer (the inverse of from_index)
+    pub fn index(&self) -> u8 {
+        match self {
+            Tape::W6 => 0x01,
+            Tape::W9 => 0x02,
+            Tape::W12 => 0x03,
+            Tape::W18 => 0x04,
+            Tape::W24 => 0x05,
+            Tape::W36 => 0x06,
+            Tape::W4 => 0x0B,
+        }
+    }
+    /// Geometry of a standard laminated tape of this width
+    pub fn geometry(&self) -> TapeGeometry {
+        let printable_mm = match self {
             Tape::W4 => 2.85,  // verified
             Tape::W6 => 5.0,   // verified
             Tape::W9 => 7.0,   // verified
@@ -44,17 +99,135 @@ impl Tape {
             Tape::W24 => 20.0, // verified
             Tape::W36 => 26.0, // verified
         };
-        let w = mm_to_px(w);
+        TapeGeometry {
+            width_mm: self.width_mm() as f32,
+            printable_mm,
+            // The printer aligns the raster rows to the tape by itself: the
+            // outline of the test pattern, at the first and the last dots of
+            // the rows, is printed on the tape with the printable_mm above.
+            head_offset_dots: 0,
+            // Not measured yet
+            feed_margin_mm: None,
+            cut_margin_mm: None,
+        }
+    }
+    pub fn width_px(&self) -> i32 {
+        self.geometry().printable_px() as i32
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TapeKind {
+    Standard,
+    Transparent,
+    IronOn,
+    Magnet,
+    HeatShrinkTube,
+    Unknown,
+}
+impl FromStr for TapeKind {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "standard" => TapeKind::Standard,
+            "clear" | "transparent" => TapeKind::Transparent,
+            "iron-on" => TapeKind::IronOn,
+            "magnet" => TapeKind::Magnet,
+            "tube" => TapeKind::HeatShrinkTube,
+            _ => {
+                return Err(anyhow!(
+                    "Unknown tape kind: {s} (standard, clear, iron-on, magnet or tube)"
+                ))
+            }
+        })
+    }
+}
+
+/// Information about the loaded cassette reported in the status
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Cassette {
+    /// byte 0x03 of the status, which tells the width of the tape
+    pub width_index: u8,
+    /// bytes 0x04..0x0B of the status. Their meaning is not known yet, but
+    /// they are captured here to identify cassettes other than standard ones.
+    pub id: [u8; 7],
+}
+impl Cassette {
+    /// The ids in the captured status packets and the kinds of the cassettes.
+    /// Only standard laminated tapes have been captured so far. The id of
+    /// another cassette is printed when it is detected, and can be added here.
+    const KNOWN_IDS: &'static [([u8; 7], TapeKind)] = &[(
+        [0x00, 0x00, 0x00, 0x00, 0x40, 0x00, 0x00],
+        TapeKind::Standard,
+    )];
+    /// The kind should be given explicitly for cassettes not in KNOWN_IDS.
+    pub fn kind(&self) -> TapeKind {
+        Self::KNOWN_IDS
+            .iter()
+            .find(|(id, _)| *id == self.id)
+            .map_or(TapeKind::Unknown, |(_, kind)| *kind)
+    }
+}
+
+/// Physical dimensions of a tape and where it is printed on the head
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TapeGeometry {
+    /// Physical width of the tape
+    pub width_mm: f32,
+    /// Width of the area that can be printed, at the center of the tape
+    pub printable_mm: f32,
+    /// Number of blank dots in a raster row before the printable area
+    pub head_offset_dots: usize,
+    /// Blank tape before the first raster row, or None if not measured
+    pub feed_margin_mm: Option<f32>,
+    /// Blank tape after the last raster row, or None if not measured
+    pub cut_margin_mm: Option<f32>,
+}
+impl TapeGeometry {
+    pub fn width_px(&self) -> usize {
+        mm_to_px(self.width_mm) as usize
+    }
+    pub fn printable_px(&self) -> usize {
+        let w = mm_to_px(self.printable_mm) as usize;
         // tape width in px should be multiple of 8
-        (w + 7) / 8 * 8
+        w.next_multiple_of(8)
+    }
+    /// Offset of the printable area from the edge of the tape in px
+    pub fn printable_offset_px(&self) -> usize {
+        self.width_px().saturating_sub(self.printable_px()) / 2
+    }
+    /// The margins are 0 if they are not measured
+    pub fn feed_margin_px(&self) -> usize {
+        self.feed_margin_mm.map_or(0, |mm| mm_to_px(mm) as usize)
+    }
+    pub fn cut_margin_px(&self) -> usize {
+        self.cut_margin_mm.map_or(0, |mm| mm_to_px(mm) as usize)
     }
 }
 
 #[derive(Copy, Clone, Debug)]
 pub enum PrinterStatus {
     NoTape,
-    SomeTape(Tape),
+    SomeTape(Tape, Cassette),
+    /// A cassette with a width index which is not known yet
+    UnknownTape(Cassette),
     CoverIsOpened,
     Printing,
     Unknown(PacketHeader, [u8; 20]),
 }
+
+#[test]
+fn tape_geometry() {
+    for mm in [4, 6, 9, 12, 18, 24, 36] {
+        let tape = Tape::from_mm(mm).unwrap();
+        let g = tape.geometry();
+        assert_eq!(g.width_mm, mm as f32);
+        assert!(g.printable_mm < g.width_mm);
+        assert_eq!(g.printable_px() % 8, 0);
+        // the rounded printable area should still fit on the tape
+        assert!(g.printable_px() <= g.width_px());
+        assert_eq!(tape.width_px() as usize, g.printable_px());
+        // the margins along the tape are not measured yet
+        assert_eq!((g.feed_margin_px(), g.cut_margin_px()), (0, 0));
+    }
+}
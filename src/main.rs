@@ -1,12 +1,16 @@
-#![feature(new_uninit)]
-#![feature(slice_take)]
-#![feature(exclusive_range_pattern)]
-
 use anyhow::Result;
 use argh::FromArgs;
 use sr5900p::analyzer::analyze_tcp_data;
+use sr5900p::emulator::do_emulate;
+use sr5900p::emulator::EmulateArgs;
+use sr5900p::forward::do_forward;
+use sr5900p::forward::ForwardArgs;
 use sr5900p::print::do_print;
 use sr5900p::print::PrintArgs;
+use sr5900p::proxy::do_proxy;
+use sr5900p::proxy::ProxyArgs;
+use sr5900p::serve::do_serve;
+use sr5900p::serve::ServeArgs;
 use std::fs;
 
 #[derive(FromArgs, PartialEq, Debug)]
@@ -24,9 +28,15 @@ fn do_analyze(dump_file: &str) -> Result<()> {
 
 #[derive(FromArgs, PartialEq, Debug)]
 #[argh(subcommand)]
+// Parsed once per run, and argh can't parse a boxed PrintArgs
+#[allow(clippy::large_enum_variant)]
 enum ArgsSubCommand {
     Analyze(AnalyzeArgs),
     Print(PrintArgs),
+    Serve(ServeArgs),
+    Emulate(EmulateArgs),
+    Forward(ForwardArgs),
+    Proxy(ProxyArgs),
 }
 #[derive(Debug, FromArgs)]
 /// Reach new heights.
@@ -41,5 +51,9 @@ fn main() -> Result<()> {
     match args.nested {
         ArgsSubCommand::Analyze(args) => do_analyze(&args.tcp_data),
         ArgsSubCommand::Print(args) => do_print(&args),
+        ArgsSubCommand::Serve(args) => do_serve(&args),
+        ArgsSubCommand::Emulate(args) => do_emulate(&args),
+        ArgsSubCommand::Forward(args) => do_forward(&args),
+        ArgsSubCommand::Proxy(args) => do_proxy(&args),
     }
 }
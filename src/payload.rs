@@ -0,0 +1,371 @@
+use crate::sequence::CheckDigit;
+use anyhow::anyhow;
+use anyhow::Result;
+use std::str::FromStr;
+
+/// The resolver of GS1 Digital Link URIs run by GS1
+pub const GS1_RESOLVER: &str = "https://id.gs1.org";
+
+/// Escapes the special characters in WIFI: and MECARD: fields with backslashes
+fn escape_field(s: &str) -> String {
+    let mut escaped = String::new();
+    for c in s.chars() {
+        if matches!(c, '\\' | ';' | ',' | ':' | '"') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Escapes a text value of vCard (RFC 2426)
+fn escape_vcard(s: &str) -> String {
+    let mut escaped = String::new();
+    for c in s.chars() {
+        match c {
+            '\\' | ';' | ',' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Percent-encodes the bytes other than the unreserved characters and the
+/// given ones (RFC 3986)
+fn percent_encode(s: &str, allowed: &str) -> String {
+    let mut encoded = String::new();
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) || allowed.as_bytes().contains(&b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{b:02X}"));
+        }
+    }
+    encoded
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum WifiSecurity {
+    /// WPA, WPA2 or WPA3 personal
+    #[default]
+    Wpa,
+    Wep,
+    /// An open network
+    None,
+}
+impl FromStr for WifiSecurity {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "wpa" | "wpa2" | "wpa3" => WifiSecurity::Wpa,
+            "wep" => WifiSecurity::Wep,
+            "nopass" | "none" => WifiSecurity::None,
+            _ => return Err(anyhow!("Unknown Wi-Fi security: {s} (wpa, wep or nopass)")),
+        })
+    }
+}
+
+/// A Wi-Fi network to join, which phones understand when scanned
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Wifi {
+    pub ssid: String,
+    pub password: Option<String>,
+    pub security: WifiSecurity,
+    pub hidden: bool,
+}
+impl Wifi {
+    fn data(&self) -> Result<String> {
+        let t = match self.security {
+            WifiSecurity::Wpa => "WPA",
+            WifiSecurity::Wep => "WEP",
+            WifiSecurity::None => "nopass",
+        };
+        let mut s = format!("WIFI:T:{t};S:{};", escape_field(&self.ssid));
+        match (&self.password, self.security) {
+            (None, WifiSecurity::None) => {}
+            (Some(_), WifiSecurity::None) => {
+                return Err(anyhow!("A password is given for an open Wi-Fi network"))
+            }
+            (None, _) => return Err(anyhow!("Please specify the password of the Wi-Fi network")),
+            (Some(password), _) => s.push_str(&format!("P:{};", escape_field(password))),
+        }
+        if self.hidden {
+            s.push_str("H:true;");
+        }
+        s.push(';');
+        Ok(s)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum ContactFormat {
+    /// vCard 3.0, which is understood widely
+    #[default]
+    VCard,
+    /// MECARD, which is shorter and fits smaller codes
+    MeCard,
+}
+impl FromStr for ContactFormat {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "vcard" => ContactFormat::VCard,
+            "mecard" => ContactFormat::MeCard,
+            _ => return Err(anyhow!("Unknown contact format: {s} (vcard or mecard)")),
+        })
+    }
+}
+
+/// A contact to be added to the address book
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub struct Contact {
+    pub name: String,
+    pub org: Option<String>,
+    pub tel: Option<String>,
+    pub email: Option<String>,
+    pub url: Option<String>,
+    pub format: ContactFormat,
+}
+impl Contact {
+    fn fields(&self) -> [(&'static str, &Option<String>); 4] {
+        [
+            ("ORG", &self.org),
+            ("TEL", &self.tel),
+            ("EMAIL", &self.email),
+            ("URL", &self.url),
+        ]
+    }
+    fn data(&self) -> String {
+        match self.format {
+            ContactFormat::VCard => {
+                let name = escape_vcard(&self.name);
+                let mut lines = vec![
+                    "BEGIN:VCARD".to_string(),
+                    "VERSION:3.0".to_string(),
+                    format!("N:{name};;;;"),
+                    format!("FN:{name}"),
+                ];
+                for (key, value) in self.fields() {
+                    if let Some(value) = value {
+                        // URL is not a text value, so it is not escaped
+                        let value = if key == "URL" {
+                            value.clone()
+                        } else {
+                            escape_vcard(value)
+                        };
+                        lines.push(format!("{key}:{value}"));
+                    }
+                }
+                lines.push("END:VCARD".to_string());
+                lines.join("\r\n")
+            }
+            ContactFormat::MeCard => {
+                let mut s = format!("MECARD:N:{};", escape_field(&self.name));
+                for (key, value) in self.fields() {
+                    if let Some(value) = value {
+                        s.push_str(&format!("{key}:{};", escape_field(value)));
+                    }
+                }
+                s.push(';');
+                s
+            }
+        }
+    }
+}
+
+/// A web page. https:// is added if no scheme is given.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Url(pub String);
+impl Url {
+    fn data(&self) -> Result<String> {
+        let url = self.0.trim();
+        if url.is_empty() {
+            return Err(anyhow!("The URL is empty"));
+        }
+        let url = if url.contains("://") {
+            url.to_string()
+        } else {
+            format!("https://{url}")
+        };
+        // spaces and non-ASCII characters are not allowed in URIs
+        Ok(percent_encode(&url, ":/?#[]@!$&'()*+,;=%"))
+    }
+    fn label(&self) -> String {
+        let url = self.0.trim();
+        let url = url.split_once("://").map(|(_, s)| s).unwrap_or(url);
+        url.strip_suffix('/').unwrap_or(url).to_string()
+    }
+}
+
+/// A product identified by a GTIN, optionally with its batch or serial
+/// number, as a GS1 Digital Link URI
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Gs1DigitalLink {
+    /// GTIN-8, 12, 13 or 14, including the check digit
+    pub gtin: String,
+    /// AI (10)
+    pub lot: Option<String>,
+    /// AI (21)
+    pub serial: Option<String>,
+}
+impl Gs1DigitalLink {
+    /// The elements as (AI, value), with the GTIN padded to 14 digits
+    fn elements(&self) -> Result<Vec<(&'static str, String)>> {
+        let gtin = &self.gtin;
+        if !matches!(gtin.len(), 8 | 12 | 13 | 14) || !gtin.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(anyhow!("A GTIN should be 8, 12, 13 or 14 digits: {gtin}"));
+        }
+        let (digits, check) = gtin.split_at(gtin.len() - 1);
+        let expected = CheckDigit::Gs1.calc(digits)?;
+        if check != expected.to_string() {
+            return Err(anyhow!(
+                "The check digit of GTIN {gtin} should be {expected}"
+            ));
+        }
+        let mut elements = vec![("01", format!("{gtin:0>14}"))];
+        for (ai, value) in [("10", &self.lot), ("21", &self.serial)] {
+            let Some(value) = value else {
+                continue;
+            };
+            // GS1 AI encodable character set 82
+            if value.is_empty()
+                || value.len() > 20
+                || !value
+                    .bytes()
+                    .all(|b| b.is_ascii_graphic() && !b"#$@[\\]^`{|}~".contains(&b))
+            {
+                return Err(anyhow!(
+                    "The value of AI ({ai}) should be 1-20 characters of the GS1 character set: {value}"
+                ));
+            }
+            elements.push((ai, value.clone()));
+        }
+        Ok(elements)
+    }
+    fn data(&self) -> Result<String> {
+        let mut uri = GS1_RESOLVER.to_string();
+        for (ai, value) in self.elements()? {
+            uri.push_str(&format!("/{ai}/{}", percent_encode(&value, "")));
+        }
+        Ok(uri)
+    }
+    /// The human readable interpretation, e.g. "(01) 04912345678904 (10) A1"
+    fn label(&self) -> Result<String> {
+        Ok(self
+            .elements()?
+            .iter()
+            .map(|(ai, value)| format!("({ai}) {value}"))
+            .collect::<Vec<_>>()
+            .join(" "))
+    }
+}
+
+/// A structured payload of a 2D code, with the text to show next to it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Payload {
+    Wifi(Wifi),
+    Contact(Contact),
+    Url(Url),
+    Gs1DigitalLink(Gs1DigitalLink),
+}
+impl Payload {
+    /// The string to encode
+    pub fn data(&self) -> Result<String> {
+        match self {
+            Payload::Wifi(wifi) => wifi.data(),
+            Payload::Contact(contact) => Ok(contact.data()),
+            Payload::Url(url) => url.data(),
+            Payload::Gs1DigitalLink(link) => link.data(),
+        }
+    }
+    /// The human readable text for the label
+    pub fn label(&self) -> Result<String> {
+        match self {
+            Payload::Wifi(wifi) => Ok(wifi.ssid.clone()),
+            Payload::Contact(contact) => Ok(contact.name.clone()),
+            Payload::Url(url) => Ok(url.label()),
+            Payload::Gs1DigitalLink(link) => link.label(),
+        }
+    }
+}
+
+#[test]
+fn payloads() {
+    let wifi = Wifi {
+        ssid: r#"My "Home";Net"#.to_string(),
+        password: Some(r"p@ss:w\rd,1".to_string()),
+        security: WifiSecurity::Wpa,
+        hidden: false,
+    };
+    let payload = Payload::Wifi(wifi.clone());
+    assert_eq!(
+        payload.data().unwrap(),
+        r#"WIFI:T:WPA;S:My \"Home\"\;Net;P:p@ss\:w\\rd\,1;;"#
+    );
+    assert_eq!(payload.label().unwrap(), r#"My "Home";Net"#);
+    let open = Wifi {
+        password: None,
+        security: WifiSecurity::None,
+        hidden: true,
+        ..wifi.clone()
+    };
+    assert_eq!(
+        Payload::Wifi(open).data().unwrap(),
+        r#"WIFI:T:nopass;S:My \"Home\"\;Net;H:true;;"#
+    );
+    assert!(Payload::Wifi(Wifi {
+        password: None,
+        ..wifi
+    })
+    .data()
+    .is_err());
+
+    let contact = Contact {
+        name: "Doe, John".to_string(),
+        org: Some("ACME; Inc.".to_string()),
+        tel: Some("+81-3-1234-5678".to_string()),
+        url: Some("https://example.com/a,b".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(
+        Payload::Contact(contact.clone()).data().unwrap(),
+        "BEGIN:VCARD\r\nVERSION:3.0\r\nN:Doe\\, John;;;;\r\nFN:Doe\\, John\r\nORG:ACME\\; Inc.\r\nTEL:+81-3-1234-5678\r\nURL:https://example.com/a,b\r\nEND:VCARD"
+    );
+    let mecard = Contact {
+        format: ContactFormat::MeCard,
+        ..contact
+    };
+    assert_eq!(
+        Payload::Contact(mecard.clone()).data().unwrap(),
+        r"MECARD:N:Doe\, John;ORG:ACME\; Inc.;TEL:+81-3-1234-5678;URL:https\://example.com/a\,b;;"
+    );
+    assert_eq!(Payload::Contact(mecard).label().unwrap(), "Doe, John");
+
+    let url = Payload::Url(Url("example.com/a b/ü/".to_string()));
+    assert_eq!(url.data().unwrap(), "https://example.com/a%20b/%C3%BC/");
+    assert_eq!(url.label().unwrap(), "example.com/a b/ü");
+
+    let link = Gs1DigitalLink {
+        gtin: "4912345678904".to_string(),
+        lot: Some("A1/2".to_string()),
+        serial: None,
+    };
+    let payload = Payload::Gs1DigitalLink(link.clone());
+    assert_eq!(
+        payload.data().unwrap(),
+        "https://id.gs1.org/01/04912345678904/10/A1%2F2"
+    );
+    assert_eq!(payload.label().unwrap(), "(01) 04912345678904 (10) A1/2");
+    for gtin in ["4912345678905", "491234567890", "49123456789O4"] {
+        let link = Gs1DigitalLink {
+            gtin: gtin.to_string(),
+            ..link.clone()
+        };
+        assert!(Payload::Gs1DigitalLink(link).data().is_err());
+    }
+}
@@ -0,0 +1,267 @@
+use crate::display::TapeDisplay;
+use crate::TapeGeometry;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use std::fs::File;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PreviewColor {
+    Black,
+    White,
+    Red,
+    Blue,
+    Green,
+    Yellow,
+    Gold,
+    Silver,
+    Clear,
+}
+impl PreviewColor {
+    pub fn rgb(&self) -> [u8; 3] {
+        match self {
+            PreviewColor::Black => [0, 0, 0],
+            PreviewColor::White => [255, 255, 255],
+            PreviewColor::Red => [200, 30, 40],
+            PreviewColor::Blue => [30, 80, 180],
+            PreviewColor::Green => [40, 150, 70],
+            PreviewColor::Yellow => [250, 220, 40],
+            PreviewColor::Gold => [200, 160, 60],
+            PreviewColor::Silver => [190, 190, 195],
+            // There is no transparency in the preview, so a light gray is used
+            PreviewColor::Clear => [235, 235, 235],
+        }
+    }
+}
+impl FromStr for PreviewColor {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s.to_lowercase().as_str() {
+            "black" => PreviewColor::Black,
+            "white" => PreviewColor::White,
+            "red" => PreviewColor::Red,
+            "blue" => PreviewColor::Blue,
+            "green" => PreviewColor::Green,
+            "yellow" => PreviewColor::Yellow,
+            "gold" => PreviewColor::Gold,
+            "silver" => PreviewColor::Silver,
+            "clear" | "transparent" => PreviewColor::Clear,
+            _ => return Err(anyhow!("Unknown color: {s}")),
+        })
+    }
+}
+
+/// Ink and tape colors of a cassette, given as "<ink>-on-<tape>" (e.g. "black-on-yellow")
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PreviewColors {
+    pub ink: PreviewColor,
+    pub tape: PreviewColor,
+}
+impl FromStr for PreviewColors {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        let (ink, tape) = s
+            .split_once("-on-")
+            .context(anyhow!("Expected <ink>-on-<tape> but got {s}"))?;
+        Ok(Self {
+            ink: ink.parse()?,
+            tape: tape.parse()?,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PreviewOptions {
+    /// Each printer dot will be a scale x scale square in the preview
+    pub scale: usize,
+    /// If specified, the whole tape width is rendered with these colors and
+    /// the non-printable margins are shaded. Otherwise, only the printable
+    /// area is rendered in black and white.
+    pub colors: Option<PreviewColors>,
+}
+impl Default for PreviewOptions {
+    fn default() -> Self {
+        Self {
+            scale: 1,
+            colors: None,
+        }
+    }
+}
+
+/// The whole tape segment for a label: td is placed at (ofs_x, ofs_y), with
+/// the feed and cut margins before and after it along the tape.
+pub(crate) struct TapeArea {
+    pub width: usize,
+    pub height: usize,
+    pub ofs_x: usize,
+    pub ofs_y: usize,
+}
+impl TapeArea {
+    pub fn new(td: &TapeDisplay, g: &TapeGeometry) -> Self {
+        let height = std::cmp::max(g.width_px(), g.printable_offset_px() + td.height);
+        Self {
+            width: g.feed_margin_px() + td.width + g.cut_margin_px(),
+            height,
+            ofs_x: g.feed_margin_px(),
+            ofs_y: g.printable_offset_px(),
+        }
+    }
+}
+
+pub(crate) fn shaded(c: [u8; 3]) -> [u8; 3] {
+    c.map(|v| (v as u16 * 3 / 4) as u8)
+}
+
+/// Returns the preview as rows of RGBA pixels (before scaling).
+fn render_rows(
+    td: &TapeDisplay,
+    geometry: &TapeGeometry,
+    opts: &PreviewOptions,
+) -> Vec<Vec<[u8; 4]>> {
+    let rgba = |c: [u8; 3]| [c[0], c[1], c[2], 255];
+    let colors = if let Some(colors) = opts.colors {
+        colors
+    } else {
+        return td
+            .framebuffer
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|c| {
+                        if *c {
+                            [0, 0, 0, 255]
+                        } else {
+                            [255, 255, 255, 255]
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+    };
+    let ink = rgba(colors.ink.rgb());
+    let tape_color = rgba(colors.tape.rgb());
+    let margin_color = rgba(shaded(colors.tape.rgb()));
+    let area = TapeArea::new(td, geometry);
+    (0..area.height)
+        .map(|y| {
+            (0..area.width)
+                .map(|x| {
+                    if !(area.ofs_y..area.ofs_y + td.height).contains(&y)
+                        || !(area.ofs_x..area.ofs_x + td.width).contains(&x)
+                    {
+                        margin_color
+                    } else if td.get_pixel(x - area.ofs_x, y - area.ofs_y) {
+                        ink
+                    } else {
+                        tape_color
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+pub fn encode_preview_png<W: Write>(
+    w: W,
+    td: &TapeDisplay,
+    geometry: &TapeGeometry,
+    opts: &PreviewOptions,
+) -> Result<()> {
+    if opts.scale == 0 {
+        return Err(anyhow!("Preview scale should be 1 or larger"));
+    }
+    let rows = render_rows(td, geometry, opts);
+    let width = rows.first().map_or(0, |row| row.len()) * opts.scale;
+    let height = rows.len() * opts.scale;
+    let mut encoder = png::Encoder::new(w, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    // sRGB
+    encoder.set_source_gamma(png::ScaledFloat::new(1.0 / 2.2));
+    encoder.set_source_chromaticities(png::SourceChromaticities::new(
+        (0.31270, 0.32900),
+        (0.64000, 0.33000),
+        (0.30000, 0.60000),
+        (0.15000, 0.06000),
+    ));
+    let mut writer = encoder.write_header()?;
+    let data: Vec<u8> = rows
+        .iter()
+        .flat_map(|row| std::iter::repeat_n(row, opts.scale))
+        .flat_map(|row| row.iter())
+        .flat_map(|c| std::iter::repeat_n(c, opts.scale))
+        .flat_map(|c| *c)
+        .collect();
+    writer.write_image_data(&data)?;
+    Ok(())
+}
+
+pub fn write_preview_png(
+    path: &Path,
+    td: &TapeDisplay,
+    geometry: &TapeGeometry,
+    opts: &PreviewOptions,
+) -> Result<()> {
+    let file = File::create(path).context(anyhow!("Failed to create {path:?}"))?;
+    encode_preview_png(BufWriter::new(file), td, geometry, opts)
+}
+
+#[test]
+fn preview_png() {
+    let colors: PreviewColors = "White-on-clear".parse().unwrap();
+    assert_eq!(colors.ink, PreviewColor::White);
+    assert_eq!(colors.tape, PreviewColor::Clear);
+    assert!("black".parse::<PreviewColors>().is_err());
+    assert!("black-on-pink".parse::<PreviewColors>().is_err());
+
+    let mut td = TapeDisplay::new(3, 2);
+    td.set_pixel(1, 0, true);
+    let geometry = crate::Tape::W12.geometry();
+    let path = std::env::temp_dir().join(format!("sr5900p_preview_{}.png", std::process::id()));
+    let read = |opts: &PreviewOptions| {
+        write_preview_png(&path, &td, &geometry, opts).unwrap();
+        let decoder = png::Decoder::new(File::open(&path).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf).unwrap();
+        (info.width as usize, info.height as usize, buf)
+    };
+    // black and white, scaled
+    let (width, height, buf) = read(&PreviewOptions {
+        scale: 2,
+        colors: None,
+    });
+    assert_eq!((width, height), (6, 4));
+    let pixel = |x: usize, y: usize| &buf[(y * width + x) * 4..(y * width + x) * 4 + 4];
+    assert_eq!(pixel(2, 0), [0, 0, 0, 255]);
+    assert_eq!(pixel(3, 1), [0, 0, 0, 255]);
+    assert_eq!(pixel(0, 0), [255, 255, 255, 255]);
+    assert_eq!(pixel(2, 2), [255, 255, 255, 255]);
+    // the whole tape with the margins
+    let opts = PreviewOptions {
+        scale: 1,
+        colors: Some("red-on-white".parse().unwrap()),
+    };
+    let (width, height, buf) = read(&opts);
+    let area = TapeArea::new(&td, &geometry);
+    assert_eq!((width, height), (area.width, area.height));
+    let pixel = |x: usize, y: usize| &buf[(y * width + x) * 4..(y * width + x) * 4 + 3];
+    assert_eq!(pixel(area.ofs_x + 1, area.ofs_y), PreviewColor::Red.rgb());
+    assert_eq!(pixel(area.ofs_x, area.ofs_y), PreviewColor::White.rgb());
+    assert_eq!(pixel(0, 0), shaded(PreviewColor::White.rgb()));
+    assert!(write_preview_png(
+        &path,
+        &td,
+        &geometry,
+        &PreviewOptions {
+            scale: 0,
+            colors: None
+        }
+    )
+    .is_err());
+    std::fs::remove_file(&path).unwrap();
+}
@@ -1,11 +1,46 @@
 use crate::analyzer::analyze_tcp_data;
+use crate::banner::Banner;
+use crate::banner::DEFAULT_MAX_JOB_LENGTH_MM;
 use crate::display::TapeDisplay;
+use crate::encoder::render_rows;
+use crate::encoder::JobEncoder;
+use crate::encoder::RowSource;
+use crate::layout::Align;
+use crate::layout::LengthOptions;
+use crate::layout::Mm;
+use crate::payload::Contact;
+use crate::payload::ContactFormat;
+use crate::payload::Gs1DigitalLink;
+use crate::payload::Payload;
+use crate::payload::Url;
+use crate::payload::Wifi;
+use crate::payload::WifiSecurity;
+use crate::preview::write_preview_png;
+use crate::preview::PreviewColor;
+use crate::preview::PreviewColors;
+use crate::preview::PreviewOptions;
 use crate::protocol::notify_data_stream;
+use crate::protocol::printer_addr;
+use crate::protocol::udp_bind_addr;
 use crate::protocol::StartPrintRequest;
 use crate::protocol::StatusRequest;
 use crate::protocol::StopPrintRequest;
+use crate::qr::CodeOptions;
+use crate::qr::QrEcc;
+use crate::qr::Symbology;
+use crate::sequence::load_next_number;
+use crate::sequence::save_next_number;
+use crate::sequence::CheckDigit;
+use crate::sequence::Sequence;
+use crate::sequence::SequenceFormat;
+use crate::svg::rasterize_svg;
+use crate::svg::write_svg;
+use crate::terminal::print_to_terminal;
+use crate::terminal::TermPreviewMode;
 use crate::PrinterStatus;
 use crate::Tape;
+use crate::TapeGeometry;
+use crate::TapeKind;
 use anyhow::anyhow;
 use anyhow::Context;
 use anyhow::Result;
@@ -25,106 +60,109 @@ use embedded_graphics::text::Baseline;
 use embedded_graphics::text::Text;
 use embedded_graphics::text::TextStyleBuilder;
 use embedded_graphics::Drawable;
-use image::Luma;
-use qrcode::QrCode;
 //use regex::Regex;
-//use std::fs;
-use std::fs::File;
+use std::fs;
 use std::io::prelude::Write;
 use std::io::BufWriter;
 use std::net::TcpStream;
 use std::net::UdpSocket;
-use std::num::Wrapping;
 use std::path::Path;
 use std::thread;
 use std::time;
 
+pub const DPI: f32 = 360.0;
+const MM_TO_INCH: f32 = 10.0 / 254.0;
+
 pub fn mm_to_px(mm: f32) -> i32 {
-    const DPI: f32 = 360.0;
-    const MM_TO_INCH: f32 = 10.0 / 254.0;
     (mm * DPI * MM_TO_INCH).floor() as i32
 }
 
-fn print_tcp_data(device_ip: &str, data: &[u8]) -> Result<()> {
-    let socket = UdpSocket::bind("0.0.0.0:0").context("failed to bind")?;
-    let info = StatusRequest::send(&socket, device_ip)?;
-    println!("{:?}", info);
-    if let PrinterStatus::SomeTape(t) = info {
-        println!("Tape is {:?}, start printing...", t);
-    } else {
-        println!("Unexpected state. Aborting...");
-        std::process::exit(1);
-    }
-    StartPrintRequest::send(&socket, device_ip)?;
-    thread::sleep(time::Duration::from_millis(500));
-    let mut stream = TcpStream::connect(device_ip.to_string() + ":9100")?;
-    thread::sleep(time::Duration::from_millis(500));
-    notify_data_stream(&socket, device_ip)?;
-    thread::sleep(time::Duration::from_millis(500));
-    stream.write_all(data)?;
-
-    println!("Print data is sent. Waiting...");
-    loop {
-        thread::sleep(time::Duration::from_millis(500));
+pub fn px_to_mm(px: usize) -> f32 {
+    px as f32 / DPI / MM_TO_INCH
+}
+
+/// A print session with the printer, in which one or more labels can be printed.
+pub struct PrintSession<'a> {
+    device_ip: &'a str,
+    socket: UdpSocket,
+    stream: TcpStream,
+}
+impl<'a> PrintSession<'a> {
+    pub fn open(device_ip: &'a str) -> Result<Self> {
+        let socket = UdpSocket::bind(udp_bind_addr(device_ip)).context("failed to bind")?;
         let info = StatusRequest::send(&socket, device_ip)?;
         println!("{:?}", info);
-        if let PrinterStatus::Printing = info {
-            continue;
+        if let PrinterStatus::SomeTape(t, _) = info {
+            println!("Tape is {:?}, start printing...", t);
+        } else if let PrinterStatus::UnknownTape(c) = info {
+            println!("Tape is unknown ({:?}), start printing anyway...", c);
+        } else {
+            return Err(anyhow!("Unexpected state: {:?}. Aborting...", info));
         }
-        break;
+        StartPrintRequest::send(&socket, device_ip)?;
+        thread::sleep(time::Duration::from_millis(500));
+        let stream = TcpStream::connect(printer_addr(device_ip))?;
+        thread::sleep(time::Duration::from_millis(500));
+        notify_data_stream(&socket, device_ip)?;
+        thread::sleep(time::Duration::from_millis(500));
+        Ok(Self {
+            device_ip,
+            socket,
+            stream,
+        })
     }
-
-    StopPrintRequest::send(&socket, device_ip)?;
-
-    Ok(())
-}
-
-fn gen_tcp_data(td: &TapeDisplay) -> Result<Vec<u8>> {
-    let mut tcp_data: Vec<u8> = Vec::new();
-    tcp_data.append(&mut vec![27, 123, 3, 64, 64, 125]);
-    tcp_data.append(&mut vec![27, 123, 7, 123, 0, 0, 83, 84, 34, 125]);
-    tcp_data.append(&mut vec![27, 123, 7, 67, 2, 2, 1, 1, 73, 125]); // half-cut?
-    tcp_data.append(&mut vec![27, 123, 4, 68, 5, 73, 125]);
-    tcp_data.append(&mut vec![27, 123, 3, 71, 71, 125]);
-
-    let mut tape_len_bytes = (td.width as u32 + 4/* safe margin */)
-        .to_le_bytes()
-        .to_vec();
-    let mut cmd_bytes = vec![76];
-    cmd_bytes.append(&mut tape_len_bytes);
-    let csum = cmd_bytes
-        .iter()
-        .map(|v| Wrapping(*v))
-        .sum::<Wrapping<u8>>()
-        .0;
-    cmd_bytes.push(csum);
-    cmd_bytes.push(0x7d);
-    tcp_data.append(&mut vec![0x1b, 0x7b, cmd_bytes.len() as u8]);
-    tcp_data.append(&mut cmd_bytes);
-
-    tcp_data.append(&mut vec![27, 123, 5, 84, 42, 0, 126, 125]);
-    tcp_data.append(&mut vec![27, 123, 4, 72, 5, 77, 125]);
-    tcp_data.append(&mut vec![27, 123, 4, 115, 0, 115, 125]);
-
-    let row_bytes = (td.height + 7) / 8;
-    for y in 0..td.width {
-        tcp_data.append(&mut vec![0x1b, 0x2e, 0, 0, 0, 1]);
-        tcp_data.append(&mut (td.height as u16).to_le_bytes().to_vec());
-        for xb in 0..row_bytes {
-            let mut chunk = 0x00;
-            for dx in 0..8 {
-                let x = xb * 8 + (7 - dx);
-
-                if td.get_pixel(td.width - 1 - y, x) {
-                    chunk |= 1 << dx
-                }
+    /// Sends a label generated by gen_tcp_data and waits for the printer.
+    /// Returns the status after printing, so that the caller can check
+    /// whether the printer is ready for the next label.
+    pub fn print(&mut self, data: &[u8]) -> Result<PrinterStatus> {
+        self.stream.write_all(data)?;
+        self.wait_until_printed()
+    }
+    /// Same as print, but the data is encoded while it is sent, so the
+    /// encoded job is never held in memory as a whole. The bitmap is, unless
+    /// the source generates its rows (e.g. Banner) instead of a TapeDisplay.
+    pub fn print_stream<S: RowSource>(&mut self, job: JobEncoder<S>) -> Result<PrinterStatus> {
+        job.write_to(&mut BufWriter::new(&mut self.stream))?;
+        self.wait_until_printed()
+    }
+    fn wait_until_printed(&self) -> Result<PrinterStatus> {
+        println!("Print data is sent. Waiting...");
+        loop {
+            thread::sleep(time::Duration::from_millis(500));
+            let info = StatusRequest::send(&self.socket, self.device_ip)?;
+            println!("{:?}", info);
+            if let PrinterStatus::Printing = info {
+                continue;
             }
-            tcp_data.push(chunk);
+            return Ok(info);
         }
     }
-    tcp_data.push(0x0c); // data end
-    tcp_data.append(&mut vec![27, 123, 3, 64, 64, 125]);
-    Ok(tcp_data)
+    pub fn close(self) -> Result<()> {
+        StopPrintRequest::send(&self.socket, self.device_ip)
+    }
+    /// Closes the session after an error, so that the printer is not left
+    /// in the session, and returns the error.
+    pub fn abort(self, e: anyhow::Error) -> anyhow::Error {
+        if let Err(close_error) = self.close() {
+            eprintln!("Failed to close the print session: {close_error:#}");
+        }
+        e
+    }
+}
+
+fn print_stream<S: RowSource>(device_ip: &str, job: JobEncoder<S>) -> Result<()> {
+    let mut session = PrintSession::open(device_ip)?;
+    match session.print_stream(job) {
+        Ok(_) => session.close(),
+        Err(e) => Err(session.abort(e)),
+    }
+}
+
+/// Encodes a label into the data sent on the TCP connection of a print session.
+/// The same data is used by PrintSession and the async client. Use JobEncoder
+/// directly to send long labels without building the whole data in memory.
+pub fn gen_tcp_data(td: &TapeDisplay, geometry: &TapeGeometry) -> Result<Vec<u8>> {
+    Ok(JobEncoder::new(td, geometry)?.flatten().collect())
 }
 
 /*
@@ -359,26 +397,45 @@ fn print_qr_text(text: &str, device_ip: &str, dry_run: bool) -> Result<()> {
 }
 */
 
-fn determine_tape_width_px(args: &PrintArgs) -> Result<i32> {
-    let detected = if let Some(printer) = &args.printer {
-        let socket = UdpSocket::bind("0.0.0.0:0").context("failed to bind")?;
+/// Determines the tape from the given width and the printer, and returns its geometry.
+pub(crate) fn detect_geometry(
+    printer: Option<&str>,
+    width: Option<usize>,
+    tape_kind: Option<TapeKind>,
+) -> Result<TapeGeometry> {
+    Ok(detect_tape(printer, width, tape_kind)?.0.geometry())
+}
+
+/// Determines the tape and its kind from the given options and the cassette in the printer.
+fn detect_tape(
+    printer: Option<&str>,
+    width: Option<usize>,
+    tape_kind: Option<TapeKind>,
+) -> Result<(Tape, TapeKind)> {
+    let (detected, cassette) = if let Some(printer) = printer {
+        let socket = UdpSocket::bind(udp_bind_addr(printer)).context("failed to bind")?;
         let info = StatusRequest::send(&socket, printer)?;
         eprintln!("Tape detected: {:?}", info);
-        if let PrinterStatus::SomeTape(t) = info {
-            Some(t)
-        } else {
-            eprintln!("Failed to detect tape width. status: {:?}", info);
-            None
+        match info {
+            PrinterStatus::SomeTape(t, c) => (Some(t), Some(c)),
+            PrinterStatus::UnknownTape(c) => {
+                eprintln!("Unknown tape index {:#04X}", c.width_index);
+                (None, Some(c))
+            }
+            _ => {
+                eprintln!("Failed to detect tape width. status: {:?}", info);
+                (None, None)
+            }
         }
     } else {
-        None
+        (None, None)
     };
-    let given = if let Some(mm) = args.width {
+    let given = if let Some(mm) = width {
         Some(Tape::from_mm(mm)?)
     } else {
         None
     };
-    Ok(match (given, detected) {
+    let tape = match (given, detected) {
         (None, Some(w)) | (Some(w), None) => w,
         (Some(given), Some(detected)) => {
             if given != detected {
@@ -387,42 +444,277 @@ fn determine_tape_width_px(args: &PrintArgs) -> Result<i32> {
             given
         }
         (None, None) => return Err(anyhow!("Please specify --width or --printer")),
+    };
+    let kind = match (tape_kind, cassette.map(|c| c.kind())) {
+        (Some(kind), _) => kind,
+        (None, Some(TapeKind::Unknown)) => {
+            eprintln!(
+                "Warning: the kind of the cassette (id: {:02X?}) is unknown. Assuming a standard tape (see --tape-kind)",
+                cassette.map(|c| c.id).unwrap_or_default()
+            );
+            TapeKind::Standard
+        }
+        (None, Some(kind)) => kind,
+        (None, None) => TapeKind::Standard,
+    };
+    Ok((tape, kind))
+}
+
+/// Determines the geometry of the tape from --width, --tape-kind and --printer.
+fn determine_geometry(args: &PrintArgs) -> Result<TapeGeometry> {
+    let (tape, kind) = detect_tape(args.printer.as_deref(), args.width, args.tape_kind)?;
+    if let Some(colors) = args.preview_colors {
+        if colors.ink == PreviewColor::White
+            && (colors.tape == PreviewColor::Clear || kind == TapeKind::Transparent)
+        {
+            eprintln!(
+                "Warning: white on a clear tape is hard to read unless it is put on a dark surface"
+            );
+        }
     }
-    .width_px())
+    if kind == TapeKind::HeatShrinkTube {
+        eprintln!(
+            "Warning: the printable area of heat-shrink tubes is not measured yet. Keep the content away from the folded edges"
+        );
+    }
+    Ok(tape.geometry())
 }
 
-fn print_qr_text(args: &PrintArgs) -> Result<()> {
-    let text = args.qr_text.as_ref().expect("Please specify --qr-text");
-    let tape_width_px = determine_tape_width_px(args)? as usize;
-    let qr_td = {
-        let mut td = TapeDisplay::new(tape_width_px, tape_width_px);
-        let tape_width_px = tape_width_px as u32;
-        let code = QrCode::new(text).unwrap();
-        let image = code
-            .render::<Luma<u8>>()
-            .max_dimensions(tape_width_px, tape_width_px)
-            .build();
-        let ofs_x = (tape_width_px - image.width()) / 2;
-        let ofs_y = (tape_width_px - image.height()) / 2;
-        for (x, y, p) in image.enumerate_pixels() {
-            Rectangle::new(
-                Point::new((x + ofs_x) as i32, (y + ofs_y) as i32),
-                Size::new_equal(1),
-            )
-            .draw_styled(
-                &PrimitiveStyle::with_fill(BinaryColor::from(p.0[0] == 0)),
-                &mut td,
-            )?;
+pub(crate) fn gen_text_td(text: &str, tape_width_px: usize) -> Result<TapeDisplay> {
+    let character_style = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
+    let text_len = text.len();
+    let margin_px = 4usize;
+    let r = tape_width_px / (20 + margin_px);
+    let mut td = TapeDisplay::new(10 * text_len + margin_px, 20 + margin_px);
+    let tb = TextStyleBuilder::new();
+    let ts = tb
+        .alignment(Alignment::Center)
+        .baseline(Baseline::Middle)
+        .build();
+    Text::with_text_style(text, td.bounding_box().center(), character_style, ts).draw(&mut td)?;
+    // magnify the td as much as possible to fit the parent
+    Ok(td.scaled(r))
+}
+
+/// Places the parts from left to right, each of them centered vertically.
+fn concat_horizontally(tape_width_px: usize, parts: &[&TapeDisplay]) -> TapeDisplay {
+    let mut td = TapeDisplay::new(parts.iter().map(|p| p.width).sum(), tape_width_px);
+    let mut x = 0;
+    for p in parts {
+        td.overlay_or(p, x, td.height.saturating_sub(p.height) / 2);
+        x += p.width;
+    }
+    td
+}
+
+pub(crate) fn render_qr_text(
+    text: &str,
+    tape_width_px: usize,
+    qr: &CodeOptions,
+) -> Result<TapeDisplay> {
+    render_qr_label(text, text, tape_width_px, qr)
+}
+
+/// Renders a code of data with the text next to it
+fn render_qr_label(
+    data: &str,
+    text: &str,
+    tape_width_px: usize,
+    qr: &CodeOptions,
+) -> Result<TapeDisplay> {
+    let qr_td = qr.render(data, tape_width_px)?;
+    let text_td = gen_text_td(text, tape_width_px)?;
+    Ok(concat_horizontally(tape_width_px, &[&qr_td, &text_td]))
+}
+
+fn payload(args: &PrintArgs) -> Option<Payload> {
+    if let Some(ssid) = &args.wifi_ssid {
+        let security = args.wifi_security.unwrap_or(match args.wifi_password {
+            Some(_) => WifiSecurity::Wpa,
+            None => WifiSecurity::None,
+        });
+        Some(Payload::Wifi(Wifi {
+            ssid: ssid.clone(),
+            password: args.wifi_password.clone(),
+            security,
+            hidden: args.wifi_hidden,
+        }))
+    } else if let Some(name) = &args.contact_name {
+        Some(Payload::Contact(Contact {
+            name: name.clone(),
+            org: args.contact_org.clone(),
+            tel: args.contact_tel.clone(),
+            email: args.contact_email.clone(),
+            url: args.contact_url.clone(),
+            format: args.contact_format.unwrap_or_default(),
+        }))
+    } else if let Some(url) = &args.url {
+        Some(Payload::Url(Url(url.clone())))
+    } else {
+        args.gs1_gtin.as_ref().map(|gtin| {
+            Payload::Gs1DigitalLink(Gs1DigitalLink {
+                gtin: gtin.clone(),
+                lot: args.gs1_lot.clone(),
+                serial: args.gs1_serial.clone(),
+            })
+        })
+    }
+}
+
+fn sequence(args: &PrintArgs) -> Result<Sequence> {
+    let format: SequenceFormat = args
+        .seq_format
+        .as_ref()
+        .expect("Please specify --seq-format")
+        .parse()?;
+    Ok(Sequence {
+        format,
+        start: args.seq_start.unwrap_or(1),
+        count: args.seq_count.context("Please specify --seq-count")?,
+        step: args.seq_step.unwrap_or(1),
+        check: args.seq_check,
+    })
+}
+
+/// Prints labels with serial numbers like SRV-0001, SRV-0002, ... in a session.
+/// If --seq-state is given, the next number is recorded after each label so
+/// that an interrupted run (e.g. by running out of tape) can be resumed.
+fn print_sequence(args: &PrintArgs) -> Result<()> {
+    let seq = sequence(args)?;
+    let state = args.seq_state.as_ref().map(Path::new);
+    let next = match state {
+        Some(path) => load_next_number(path)?.unwrap_or(seq.start),
+        None => seq.start,
+    };
+    if next != seq.start {
+        println!("Resuming from {}", seq.label(next)?);
+    }
+    let geometry = determine_geometry(args)?;
+    let qr = code_options(args);
+    let labels = seq
+        .numbers_from(next)?
+        .map(|n| Ok((n, seq.label(n)?)))
+        .collect::<Result<Vec<_>>>()?;
+    if labels.is_empty() {
+        println!("All labels in the sequence are printed already");
+        return Ok(());
+    }
+    if args.dry_run {
+        for (i, (_, label)) in labels.iter().enumerate() {
+            let td = render_qr_text(label, geometry.printable_px(), &qr)?;
+            prepare_label(args, &geometry, &td)?;
+            println!("[{}/{}] {label}", i + 1, labels.len());
         }
-        image.save("qrcode.png").unwrap();
-        td
+        return Ok(());
+    }
+    let mut session =
+        PrintSession::open(args.printer.as_ref().context("Please specify --printer")?)?;
+    let print_labels = |session: &mut PrintSession| -> Result<()> {
+        for (i, (n, label)) in labels.iter().enumerate() {
+            let td = render_qr_text(label, geometry.printable_px(), &qr)?;
+            let td = prepare_label(args, &geometry, &td)?;
+            println!("[{}/{}] {label}", i + 1, labels.len());
+            match session.print_stream(JobEncoder::new(&td, &geometry)?)? {
+                PrinterStatus::SomeTape(..) | PrinterStatus::UnknownTape(..) => {
+                    if let Some(path) = state {
+                        save_next_number(path, n + seq.step)?;
+                    }
+                }
+                status => {
+                    return Err(anyhow!(
+                        "Stopped while printing {label}: {status:?}. Please run the same command again to resume{}",
+                        if state.is_some() { "" } else { " (with --seq-state)" }
+                    ));
+                }
+            }
+        }
+        Ok(())
+    };
+    match print_labels(&mut session) {
+        Ok(()) => session.close(),
+        Err(e) => Err(session.abort(e)),
+    }
+}
+
+/// Prints a long text as tall as the tape. A banner longer than
+/// --banner-max-length is printed as segments in consecutive jobs,
+/// with cut marks to join them.
+fn print_banner(args: &PrintArgs) -> Result<()> {
+    let text = args.banner.as_ref().expect("Please specify --banner");
+    let geometry = determine_geometry(args)?;
+    let mut banner = Banner::new(text, geometry.printable_px(), &length_options(args))?;
+    let max_length = args
+        .banner_max_length
+        .unwrap_or(Mm(DEFAULT_MAX_JOB_LENGTH_MM));
+    let segments = banner.segments(max_length.px())?;
+    println!(
+        "The banner is {:.1} mm long, printed in {} segment(s)",
+        px_to_mm(banner.width()),
+        segments.len()
+    );
+    if args.dry_run {
+        // The previews show the first segment, which is at most max_length
+        let td = render_rows(banner.segment(&segments[0]));
+        write_previews(args, &geometry, &td)?;
+        for segment in &segments {
+            let data: Vec<u8> = JobEncoder::new(banner.segment(segment), &geometry)?
+                .flatten()
+                .collect();
+            analyze_tcp_data(&data)?;
+        }
+        return Ok(());
+    }
+    let mut session =
+        PrintSession::open(args.printer.as_ref().context("Please specify --printer")?)?;
+    let mut print_segments = |session: &mut PrintSession| -> Result<()> {
+        for (i, segment) in segments.iter().enumerate() {
+            println!("Printing segment {}/{}", i + 1, segments.len());
+            match session.print_stream(JobEncoder::new(banner.segment(segment), &geometry)?)? {
+                PrinterStatus::SomeTape(..) | PrinterStatus::UnknownTape(..) => {}
+                status => return Err(anyhow!("Stopped at segment {}: {status:?}", i + 1)),
+            }
+        }
+        Ok(())
+    };
+    match print_segments(&mut session) {
+        Ok(()) => session.close(),
+        Err(e) => Err(session.abort(e)),
+    }
+}
+
+fn render_svg_image(args: &PrintArgs, tape_width_px: usize) -> Result<TapeDisplay> {
+    let path = args.svg_image.as_ref().expect("Please specify --svg-image");
+    let data = fs::read(path).context(anyhow!("Failed to read {path}"))?;
+    let height = if args.svg_fit {
+        Some(tape_width_px)
+    } else {
+        None
     };
+    let image_td = rasterize_svg(&data, height, args.svg_threshold.unwrap_or(128))?;
+    if image_td.height > tape_width_px {
+        return Err(anyhow!(
+            "The image ({:.1} mm) is taller than the printable width of the tape ({:.1} mm). Use --svg-fit to shrink it.",
+            px_to_mm(image_td.height),
+            px_to_mm(tape_width_px)
+        ));
+    }
+    Ok(if let Some(text) = &args.svg_text {
+        let text_td = gen_text_td(text, tape_width_px)?;
+        concat_horizontally(tape_width_px, &[&image_td, &text_td])
+    } else {
+        concat_horizontally(tape_width_px, &[&image_td])
+    })
+}
+
+fn render_qr_text_small(text: &str, tape_width_px: usize, qr: &CodeOptions) -> Result<TapeDisplay> {
+    let qr_td = qr.render(text, tape_width_px)?;
     let text_td = {
         let character_style = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
         let text_len = text.len();
         let margin_px = 4usize;
-        let r = tape_width_px / (20 + margin_px);
-        let mut td = TapeDisplay::new(10 * text_len + margin_px, 20 + margin_px);
+        let text_width = 10 * text_len + margin_px;
+        let r = std::cmp::min(tape_width_px / text_width, 8);
+        let mut td = TapeDisplay::new(text_width, 20+margin_px);
         let tb = TextStyleBuilder::new();
         let ts = tb
             .alignment(Alignment::Center)
@@ -431,115 +723,167 @@ fn print_qr_text(args: &PrintArgs) -> Result<()> {
         Text::with_text_style(text, td.bounding_box().center(), character_style, ts)
             .draw(&mut td)?;
         // magnify the td as much as possible to fit the parent
-        td.scaled(r)
+        td.rotated().scaled(r)
     };
-    let mut td = TapeDisplay::new(qr_td.width + text_td.width, tape_width_px);
+    let mut td = TapeDisplay::new(qr_td.width * 9 / 10 + text_td.width, tape_width_px);
     td.overlay_or(&qr_td, 0, (td.height - qr_td.height) / 2);
-    td.overlay_or(&text_td, qr_td.width, (td.height - text_td.height) / 2);
-    print_td(args, &td)
+    td.overlay_or(&text_td, qr_td.width * 9 / 10, (td.height - text_td.height)/2);
+    Ok(td)
 }
 
-fn print_qr_text_small(args: &PrintArgs) -> Result<()> {
-    let text = args.qr_text_small.as_ref().expect("Please specify --qr-text-small");
-    let tape_width_px = determine_tape_width_px(args)? as usize;
-    let qr_td = {
-        let mut td = TapeDisplay::new(tape_width_px, tape_width_px);
-        let tape_width_px = tape_width_px as u32;
-        let code = QrCode::new(text).unwrap();
-        let image = code
-            .render::<Luma<u8>>()
-            .max_dimensions(tape_width_px, tape_width_px)
-            .build();
-        let ofs_x = (tape_width_px - image.width()) / 2;
-        let ofs_y = (tape_width_px - image.height()) / 2;
-        for (x, y, p) in image.enumerate_pixels() {
-            Rectangle::new(
-                Point::new((x + ofs_x) as i32, (y + ofs_y) as i32),
-                Size::new_equal(1),
-            )
-            .draw_styled(
-                &PrimitiveStyle::with_fill(BinaryColor::from(p.0[0] == 0)),
-                &mut td,
-            )?;
-        }
-        image.save("qrcode.png").unwrap();
-        td
-    };
+/// Repeats the text along a label which wraps around a cable once, so that
+/// it can be read from any direction.
+fn render_cable_wrap(args: &PrintArgs, tape_width_px: usize) -> Result<TapeDisplay> {
+    let text = args
+        .cable_wrap
+        .as_ref()
+        .expect("Please specify --cable-wrap");
+    let diameter = args
+        .cable_diameter
+        .context("Please specify --cable-diameter")?;
     let text_td = {
         let character_style = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
-        let text_len = text.len();
         let margin_px = 4usize;
-        let text_width = 10 * text_len + margin_px;
-        let r = std::cmp::min(tape_width_px / text_width, 8);
-        let mut td = TapeDisplay::new(text_width, 20+margin_px);
-        let tb = TextStyleBuilder::new();
-        let ts = tb
+        let mut td = TapeDisplay::new(10 * text.len() + margin_px, 20 + margin_px);
+        let ts = TextStyleBuilder::new()
             .alignment(Alignment::Center)
             .baseline(Baseline::Middle)
             .build();
         Text::with_text_style(text, td.bounding_box().center(), character_style, ts)
             .draw(&mut td)?;
-        // magnify the td as much as possible to fit the parent
-        td.rotated().scaled(r)
+        let td = if args.wrap_rotate { td.rotated() } else { td };
+        // magnify the td as much as possible to fit in the tape width
+        let r = tape_width_px / td.height;
+        if r == 0 {
+            return Err(anyhow!(
+                "The text is too long to fit in the width of the tape ({:.1} mm)",
+                px_to_mm(tape_width_px)
+            ));
+        }
+        td.scaled(r)
     };
-    let mut td = TapeDisplay::new(qr_td.width * 9 / 10 + text_td.width, tape_width_px);
-    td.overlay_or(&qr_td, 0, (td.height - qr_td.height) / 2);
-    td.overlay_or(&text_td, qr_td.width * 9 / 10, (td.height - text_td.height)/2);
-    print_td(args, &td)
+    let circumference = mm_to_px(std::f32::consts::PI * diameter.0) as usize;
+    let repeat = match args.wrap_repeat {
+        Some(0) => return Err(anyhow!("--wrap-repeat should be 1 or larger")),
+        Some(n) => n,
+        None => std::cmp::max(circumference / text_td.width, 1),
+    };
+    let pitch = std::cmp::max(circumference / repeat, text_td.width);
+    if repeat > 1 && pitch * repeat > circumference {
+        eprintln!(
+            "Warning: {repeat} copies of the text do not fit around the cable. The label will be longer than the circumference ({:.1} mm).",
+            px_to_mm(circumference)
+        );
+    }
+    let mut td = TapeDisplay::new(std::cmp::max(pitch * repeat, circumference), tape_width_px);
+    for i in 0..repeat {
+        td.overlay_or(
+            &text_td,
+            i * pitch + (pitch - text_td.width) / 2,
+            (td.height - text_td.height) / 2,
+        );
+    }
+    Ok(td)
 }
 
-fn print_td(args: &PrintArgs, td: &TapeDisplay) -> Result<()> {
-    // Generate preview image
-    let path = Path::new(r"preview.png");
-    let file = File::create(path).unwrap();
-    let w = BufWriter::new(file);
-    let mut encoder = png::Encoder::new(w, td.width as u32, td.height as u32); // Width is 2 pixels and height is 1.
-    encoder.set_color(png::ColorType::Rgba);
-    encoder.set_depth(png::BitDepth::Eight);
-    encoder.set_source_gamma(png::ScaledFloat::from_scaled(45455)); // 1.0 / 2.2, scaled by 100000
-    encoder.set_source_gamma(png::ScaledFloat::new(1.0 / 2.2)); // 1.0 / 2.2, unscaled, but rounded
-    let source_chromaticities = png::SourceChromaticities::new(
-        // Using unscaled instantiation here
-        (0.31270, 0.32900),
-        (0.64000, 0.33000),
-        (0.30000, 0.60000),
-        (0.15000, 0.06000),
-    );
-    encoder.set_source_chromaticities(source_chromaticities);
-    let mut writer = encoder.write_header().unwrap();
-    let data: Vec<u8> = td
-        .framebuffer
-        .iter()
-        .flat_map(|row| row.iter())
-        .flat_map(|c| {
-            // data will be [RGBARGBA...]
-            if *c {
-                [0, 0, 0, 255]
-            } else {
-                [255, 255, 255, 255]
-            }
-        })
-        .collect();
-    writer.write_image_data(&data).unwrap();
+/// Prints the content twice with a blank gap between them. The gap wraps around
+/// the cable and the two halves are stuck together back to back, so the flag
+/// can be read from both sides. The second half is laid out in the reverse
+/// order so that both sides look the same relative to the cable.
+fn render_cable_flag(args: &PrintArgs, tape_width_px: usize) -> Result<TapeDisplay> {
+    let diameter = args
+        .cable_diameter
+        .context("Please specify --cable-diameter")?;
+    let mut parts = Vec::new();
+    if let Some(text) = &args.flag_qr {
+        parts.push(code_options(args).render(text, tape_width_px)?);
+    }
+    if let Some(text) = &args.flag_text {
+        parts.push(gen_text_td(text, tape_width_px)?);
+    }
+    let parts: Vec<&TapeDisplay> = parts.iter().collect();
+    let front = concat_horizontally(tape_width_px, &parts);
+    let back = {
+        let mut parts = parts;
+        parts.reverse();
+        concat_horizontally(tape_width_px, &parts)
+    };
+    let gap = mm_to_px(std::f32::consts::PI * diameter.0) as usize;
+    let mut td = TapeDisplay::new(front.width + gap + back.width, tape_width_px);
+    td.overlay_or(&front, 0, 0);
+    td.overlay_or(&back, front.width + gap, 0);
+    Ok(td)
+}
 
-    let tcp_data = gen_tcp_data(td)?;
+fn code_options(args: &PrintArgs) -> CodeOptions {
+    CodeOptions {
+        symbology: args.symbology.unwrap_or_default(),
+        ecc: args.qr_ecc,
+        min_version: args.qr_min_version,
+        micro: args.qr_micro,
+        quiet_zone: args.qr_quiet_zone,
+        module_px: args.qr_module_px,
+    }
+}
+
+fn length_options(args: &PrintArgs) -> LengthOptions {
+    LengthOptions {
+        length: args.length,
+        min_length: args.min_length,
+        margin_left: args.margin_left,
+        margin_right: args.margin_right,
+        align: args.align.unwrap_or_default(),
+    }
+}
+
+/// Applies the length options, writes the previews and returns the label to print.
+fn prepare_label(
+    args: &PrintArgs,
+    geometry: &TapeGeometry,
+    td: &TapeDisplay,
+) -> Result<TapeDisplay> {
+    let td = length_options(args).apply(td)?;
+    write_previews(args, geometry, &td)?;
+    Ok(td)
+}
+
+fn write_previews(args: &PrintArgs, geometry: &TapeGeometry, td: &TapeDisplay) -> Result<()> {
+    let opts = PreviewOptions {
+        scale: args.preview_scale.unwrap_or(1),
+        colors: args.preview_colors,
+    };
+    if !args.no_preview {
+        let path = args.preview.as_deref().unwrap_or("preview.png");
+        write_preview_png(Path::new(path), td, geometry, &opts)?;
+    }
+    if let Some(path) = &args.export_svg {
+        write_svg(Path::new(path), td, geometry, &opts)?;
+    }
+    if args.preview_term {
+        let mode = args.preview_term_mode.unwrap_or(TermPreviewMode::Auto);
+        print_to_terminal(td, geometry, mode, &opts)?;
+    }
+    Ok(())
+}
 
+fn print_td(args: &PrintArgs, geometry: &TapeGeometry, td: &TapeDisplay) -> Result<()> {
+    let td = prepare_label(args, geometry, td)?;
     if !args.dry_run {
-        print_tcp_data(
+        print_stream(
             args.printer.as_ref().context("Please specify --printer")?,
-            &tcp_data,
+            JobEncoder::new(&td, geometry)?,
         )
     } else {
-        analyze_tcp_data(&tcp_data)?;
+        analyze_tcp_data(&gen_tcp_data(&td, geometry)?)?;
         Ok(())
     }
 }
 
-fn print_test_pattern(args: &PrintArgs) -> Result<()> {
-    let tape_width_px = determine_tape_width_px(args)?;
+fn render_test_pattern(tape_width_px: usize) -> Result<TapeDisplay> {
     // td represents a tape segment
-    let mut td = TapeDisplay::new(mm_to_px(40.0) as usize, tape_width_px as usize);
-    // 1mm outline
+    let mut td = TapeDisplay::new(mm_to_px(40.0) as usize, tape_width_px);
+    // 1mm outline, along the edges of the printable area of the TapeGeometry.
+    // If the geometry is correct, the whole outline should appear on the tape.
     Rectangle::new(
         Point::new(0, 0),
         Size {
@@ -632,7 +976,7 @@ fn print_test_pattern(args: &PrintArgs) -> Result<()> {
         (td.width - text_td.width) / 2,
         (td.height - text_td.height) / 2,
     );
-    print_td(args, &td)
+    Ok(td)
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
@@ -648,9 +992,72 @@ pub struct PrintArgs {
     /// generate a label for a QR code with text
     #[argh(option)]
     qr_text_small: Option<String>,
+    /// generate a label to join a Wi-Fi network with the SSID
+    #[argh(option)]
+    wifi_ssid: Option<String>,
+    /// password of the Wi-Fi network
+    #[argh(option)]
+    wifi_password: Option<String>,
+    /// security of the Wi-Fi network: wpa, wep or nopass (default: wpa with a password, nopass without)
+    #[argh(option)]
+    wifi_security: Option<WifiSecurity>,
+    /// the Wi-Fi network does not broadcast the SSID
+    #[argh(switch)]
+    wifi_hidden: bool,
+    /// generate a contact label with the name
+    #[argh(option)]
+    contact_name: Option<String>,
+    /// organization of the contact
+    #[argh(option)]
+    contact_org: Option<String>,
+    /// phone number of the contact
+    #[argh(option)]
+    contact_tel: Option<String>,
+    /// email address of the contact
+    #[argh(option)]
+    contact_email: Option<String>,
+    /// URL of the contact
+    #[argh(option)]
+    contact_url: Option<String>,
+    /// format of the contact: vcard or mecard (default: vcard)
+    #[argh(option)]
+    contact_format: Option<ContactFormat>,
+    /// generate a label for a URL (https:// is added if no scheme is given)
+    #[argh(option)]
+    url: Option<String>,
+    /// generate a GS1 Digital Link label for a GTIN (8, 12, 13 or 14 digits)
+    #[argh(option)]
+    gs1_gtin: Option<String>,
+    /// batch or lot number of the GS1 Digital Link, AI (10)
+    #[argh(option)]
+    gs1_lot: Option<String>,
+    /// serial number of the GS1 Digital Link, AI (21)
+    #[argh(option)]
+    gs1_serial: Option<String>,
+    /// kind of the 2D codes: qr, datamatrix, datamatrix-rect or aztec (default: qr). PDF417 is not implemented yet.
+    #[argh(option)]
+    symbology: Option<Symbology>,
+    /// error correction level of QR codes: l, m, q or h (default: m)
+    #[argh(option)]
+    qr_ecc: Option<QrEcc>,
+    /// the smallest version of QR codes (1-40, or 1-4 with --qr-micro)
+    #[argh(option)]
+    qr_min_version: Option<i16>,
+    /// use Micro QR codes, which fit narrow tapes better
+    #[argh(switch)]
+    qr_micro: bool,
+    /// quiet zone around 2D codes in modules (default: 4, 2 with --qr-micro, 1 for datamatrix, 0 for aztec)
+    #[argh(option)]
+    qr_quiet_zone: Option<usize>,
+    /// size of a module of 2D codes in px, 1px = 0.07mm (default: as large as fits, at least 2px)
+    #[argh(option)]
+    qr_module_px: Option<usize>,
     /// tape width in mm (default: auto)
     #[argh(option)]
     width: Option<usize>,
+    /// kind of the tape: standard, clear, iron-on, magnet or tube (default: auto)
+    #[argh(option)]
+    tape_kind: Option<TapeKind>,
     /// do not print (just generate and analyze)
     #[argh(switch)]
     dry_run: bool,
@@ -660,18 +1067,184 @@ pub struct PrintArgs {
     /// print a test pattern
     #[argh(switch)]
     test_pattern: bool,
-    /// an IPv4 address for the printer
+    /// an IPv4 address for the printer (or host:port, e.g. for an emulator)
     #[argh(option)]
     printer: Option<String>,
+    /// path to write the preview image (default: preview.png)
+    #[argh(option)]
+    preview: Option<String>,
+    /// do not write the preview image
+    #[argh(switch)]
+    no_preview: bool,
+    /// magnification of the preview image (default: 1)
+    #[argh(option)]
+    preview_scale: Option<usize>,
+    /// render the preview on the whole tape with colors, e.g. black-on-yellow
+    #[argh(option)]
+    preview_colors: Option<PreviewColors>,
+    /// show the preview in the terminal
+    #[argh(switch)]
+    preview_term: bool,
+    /// how to show the preview in the terminal: auto, halfblock, braille, sixel or kitty
+    #[argh(option)]
+    preview_term_mode: Option<TermPreviewMode>,
+    /// export the label as an SVG file in its physical size
+    #[argh(option)]
+    export_svg: Option<String>,
+    /// print an SVG image (in its physical size, unless --svg-fit is given)
+    #[argh(option)]
+    svg_image: Option<String>,
+    /// scale the SVG image to the printable width of the tape
+    #[argh(switch)]
+    svg_fit: bool,
+    /// luminance threshold (0-255) to print a pixel of the SVG image (default: 128)
+    #[argh(option)]
+    svg_threshold: Option<u8>,
+    /// text to be placed next to the SVG image
+    #[argh(option)]
+    svg_text: Option<String>,
+    /// print a long text as tall as the tape, split into segments if needed
+    #[argh(option)]
+    banner: Option<String>,
+    /// maximum length of a job for --banner; longer banners are split into segments with cut marks (default: 1000mm)
+    #[argh(option)]
+    banner_max_length: Option<Mm>,
+    /// generate a label to wrap around a cable with the text repeated
+    #[argh(option)]
+    cable_wrap: Option<String>,
+    /// generate a cable flag label with the text
+    #[argh(option)]
+    flag_text: Option<String>,
+    /// generate a cable flag label with a QR code of the text
+    #[argh(option)]
+    flag_qr: Option<String>,
+    /// diameter of the cable, e.g. 6mm
+    #[argh(option)]
+    cable_diameter: Option<Mm>,
+    /// number of copies of the text around the cable (default: as many as fit)
+    #[argh(option)]
+    wrap_repeat: Option<usize>,
+    /// rotate the text of the cable wrap label by 90 degrees
+    #[argh(switch)]
+    wrap_rotate: bool,
+    /// print a sequence of QR code labels in this format, e.g. SRV-{:04}
+    #[argh(option)]
+    seq_format: Option<String>,
+    /// the first number of the sequence (default: 1)
+    #[argh(option)]
+    seq_start: Option<u64>,
+    /// number of labels in the sequence
+    #[argh(option)]
+    seq_count: Option<u64>,
+    /// increment between the numbers (default: 1)
+    #[argh(option)]
+    seq_step: Option<u64>,
+    /// append a check digit to the numbers: luhn or gs1
+    #[argh(option)]
+    seq_check: Option<CheckDigit>,
+    /// a file to record the next number, to resume an interrupted sequence
+    #[argh(option)]
+    seq_state: Option<String>,
+    /// fixed label length, e.g. 50mm (the printer feeds about 0.3 mm more)
+    #[argh(option)]
+    length: Option<Mm>,
+    /// minimum label length, e.g. 30mm (not with --length)
+    #[argh(option)]
+    min_length: Option<Mm>,
+    /// blank space before the content, e.g. 2mm
+    #[argh(option)]
+    margin_left: Option<Mm>,
+    /// blank space after the content, e.g. 2mm
+    #[argh(option)]
+    margin_right: Option<Mm>,
+    /// alignment of the content in a longer label: left, center or right (default: center)
+    #[argh(option)]
+    align: Option<Align>,
 }
-pub fn do_print(args: &PrintArgs) -> Result<()> {
-    if args.test_pattern {
-        print_test_pattern(args)
+/// The kinds of labels, in the order of precedence of the options
+enum Layout {
+    TestPattern,
+    QrText,
+    Payload(Payload),
+    Sequence,
+    QrTextSmall,
+    Banner,
+    SvgImage,
+    CableWrap,
+    CableFlag,
+}
+fn layout(args: &PrintArgs) -> Result<Layout> {
+    Ok(if args.test_pattern {
+        Layout::TestPattern
     } else if args.qr_text.is_some() {
-        print_qr_text(args)
+        Layout::QrText
+    } else if let Some(payload) = payload(args) {
+        Layout::Payload(payload)
+    } else if args.seq_format.is_some() {
+        Layout::Sequence
     } else if args.qr_text_small.is_some() {
-        print_qr_text_small(args)
+        Layout::QrTextSmall
+    } else if args.banner.is_some() {
+        Layout::Banner
+    } else if args.svg_image.is_some() {
+        Layout::SvgImage
+    } else if args.cable_wrap.is_some() {
+        Layout::CableWrap
+    } else if args.flag_text.is_some() || args.flag_qr.is_some() {
+        Layout::CableFlag
     } else {
-        Err(anyhow!("Please specify a print command"))
+        return Err(anyhow!("Please specify a print command"));
+    })
+}
+
+/// Renders the content of a label, before the length options are applied
+fn render_layout(args: &PrintArgs, layout: &Layout, tape_width_px: usize) -> Result<TapeDisplay> {
+    let text = |text: &Option<String>| text.clone().unwrap_or_default();
+    match layout {
+        Layout::TestPattern => render_test_pattern(tape_width_px),
+        Layout::QrText => render_qr_text(&text(&args.qr_text), tape_width_px, &code_options(args)),
+        Layout::Payload(payload) => render_qr_label(
+            &payload.data()?,
+            &payload.label()?,
+            tape_width_px,
+            &code_options(args),
+        ),
+        Layout::Sequence => {
+            let seq = sequence(args)?;
+            render_qr_text(&seq.label(seq.start)?, tape_width_px, &code_options(args))
+        }
+        Layout::QrTextSmall => render_qr_text_small(
+            &text(&args.qr_text_small),
+            tape_width_px,
+            &code_options(args),
+        ),
+        Layout::Banner => Ok(render_rows(Banner::new(
+            &text(&args.banner),
+            tape_width_px,
+            &LengthOptions::default(),
+        )?)),
+        Layout::SvgImage => render_svg_image(args, tape_width_px),
+        Layout::CableWrap => render_cable_wrap(args, tape_width_px),
+        Layout::CableFlag => render_cable_flag(args, tape_width_px),
+    }
+}
+
+/// Renders the label to print with the args, with the length options
+/// applied. A sequence is rendered as its first label, and a banner as a
+/// whole even if it is printed in segments.
+pub fn render_label(args: &PrintArgs, geometry: &TapeGeometry) -> Result<TapeDisplay> {
+    let td = render_layout(args, &layout(args)?, geometry.printable_px())?;
+    length_options(args).apply(&td)
+}
+
+pub fn do_print(args: &PrintArgs) -> Result<()> {
+    match layout(args)? {
+        Layout::Sequence => print_sequence(args),
+        Layout::Banner => print_banner(args),
+        layout => {
+            let geometry = determine_geometry(args)?;
+            let td = render_layout(args, &layout, geometry.printable_px())?;
+            print_td(args, &geometry, &td)
+        }
     }
 }
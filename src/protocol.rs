@@ -1,90 +1,284 @@
+use crate::Cassette;
 use crate::PrinterStatus;
 use crate::Tape;
 use anyhow::anyhow;
 use anyhow::Context;
 use anyhow::Result;
-use std::boxed::Box;
-use std::mem::size_of;
-use std::mem::MaybeUninit;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::SocketAddr;
 use std::net::UdpSocket;
-use std::slice;
-
-/// # Safety
-/// Implementing this trait is safe only when the target type can be converted
-/// mutually between a byte sequence of the same size, which means that no ownership
-/// nor memory references are involved.
-pub unsafe trait Sliceable: Sized + Copy + Clone {
-    fn copy_into_slice(&self) -> Box<[u8]> {
-        let mut values = Box::<[u8]>::new_uninit_slice(size_of::<Self>());
-        unsafe {
-            values.copy_from_slice(slice::from_raw_parts(
-                self as *const Self as *const MaybeUninit<u8>,
-                size_of::<Self>(),
-            ));
-            values.assume_init()
-        }
+
+/// Types that are sent to or received from the printer as bytes.
+/// Multi-byte fields are big-endian on the wire.
+pub trait Codec: Sized {
+    /// The size in bytes on the wire
+    const SIZE: usize;
+    fn encode_into(&self, buf: &mut Vec<u8>);
+    /// Decodes the first SIZE bytes of data. A PacketHeader ignores the rest
+    /// of data, while the requests reject it since it does not match the
+    /// data_size (0) in their header.
+    fn decode(data: &[u8]) -> Result<Self>;
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::SIZE);
+        self.encode_into(&mut buf);
+        buf
     }
-    fn copy_from_slice(data: &[u8]) -> Result<Self> {
-        if size_of::<Self>() > data.len() {
-            Err(anyhow!("data is too short"))
-        } else {
-            Ok(unsafe { *(data.as_ptr() as *const Self) })
-        }
+}
+
+fn be_u32_at(data: &[u8], ofs: usize) -> u32 {
+    u32::from_be_bytes([data[ofs], data[ofs + 1], data[ofs + 2], data[ofs + 3]])
+}
+
+/// The port for both the UDP commands and the TCP data stream
+pub const PRINTER_PORT: u16 = 9100;
+
+/// Returns the address to connect to the printer. The port can be given
+/// explicitly (e.g. "127.0.0.1:19100" for an emulator), otherwise PRINTER_PORT is used.
+pub fn printer_addr(device: &str) -> String {
+    // an IPv6 address without a port, with or without brackets
+    let ip = device
+        .strip_prefix('[')
+        .and_then(|d| d.strip_suffix(']'))
+        .unwrap_or(device);
+    if device.parse::<SocketAddr>().is_ok() {
+        device.to_string()
+    } else if let Ok(ip) = ip.parse::<IpAddr>() {
+        SocketAddr::new(ip, PRINTER_PORT).to_string()
+    } else if device.contains(':') {
+        // a host name with a port
+        device.to_string()
+    } else {
+        format!("{device}:{PRINTER_PORT}")
+    }
+}
+
+/// Returns the local address to bind a UDP socket to talk with the printer,
+/// in the same address family as the printer.
+pub fn udp_bind_addr(device: &str) -> &'static str {
+    match printer_addr(device).parse::<SocketAddr>() {
+        Ok(SocketAddr::V6(_)) => "[::]:0",
+        _ => "0.0.0.0:0",
     }
 }
-unsafe impl Sliceable for PacketHeader {}
-unsafe impl Sliceable for StatusRequest {}
-unsafe impl Sliceable for StartPrintRequest {}
-unsafe impl Sliceable for StopPrintRequest {}
 
-#[repr(packed)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct PacketHeader {
-    _signature: [u8; 4],  // "TPRT" for requests, "tprt" for responses
-    _const00_be: [u8; 4], // 00 00 00 00
-    _const01_be: [u8; 4], // 00 00 00 01
-    _const20_be: [u8; 4], // 00 00 00 20
-    _cmd_be: [u8; 4],
-    _data_size_be: [u8; 4],
-    _ip_addr_be: [u8; 4],
-    _token_be: [u8; 4],
+    signature: [u8; 4], // "TPRT" for requests, "tprt" for responses
+    const00: u32,       // 00 00 00 00
+    const01: u32,       // 00 00 00 01
+    const20: u32,       // 00 00 00 20
+    cmd: u32,
+    data_size: u32,
+    ip_addr: u32,
+    token: u32,
+}
+impl Codec for PacketHeader {
+    const SIZE: usize = 32;
+    fn encode_into(&self, buf: &mut Vec<u8>) {
+        buf.extend(self.signature);
+        for v in [
+            self.const00,
+            self.const01,
+            self.const20,
+            self.cmd,
+            self.data_size,
+            self.ip_addr,
+            self.token,
+        ] {
+            buf.extend(v.to_be_bytes());
+        }
+    }
+    fn decode(data: &[u8]) -> Result<Self> {
+        if data.len() < Self::SIZE {
+            return Err(anyhow!(
+                "The packet is too short ({} bytes) for a header",
+                data.len()
+            ));
+        }
+        Ok(Self {
+            signature: [data[0], data[1], data[2], data[3]],
+            const00: be_u32_at(data, 4),
+            const01: be_u32_at(data, 8),
+            const20: be_u32_at(data, 12),
+            cmd: be_u32_at(data, 16),
+            data_size: be_u32_at(data, 20),
+            ip_addr: be_u32_at(data, 24),
+            token: be_u32_at(data, 28),
+        })
+    }
 }
 impl PacketHeader {
     pub fn new_request(cmd: u32, data_size: u32) -> Self {
         Self {
-            _signature: *b"TPRT",
-            _const00_be: 0x00u32.to_be_bytes(),
-            _const01_be: 0x01u32.to_be_bytes(),
-            _const20_be: 0x20u32.to_be_bytes(),
-            _cmd_be: cmd.to_be_bytes(),
-            _data_size_be: data_size.to_be_bytes(),
-            _ip_addr_be: 0x00u32.to_be_bytes(),
-            _token_be: 0x00u32.to_be_bytes(),
+            signature: *b"TPRT",
+            const00: 0x00,
+            const01: 0x01,
+            const20: 0x20,
+            cmd,
+            data_size,
+            ip_addr: 0x00,
+            token: 0x00,
+        }
+    }
+    pub fn new_response(cmd: u32, data_size: u32) -> Self {
+        Self {
+            signature: *b"tprt",
+            ..Self::new_request(cmd, data_size)
+        }
+    }
+    pub fn signature(&self) -> [u8; 4] {
+        self.signature
+    }
+    pub fn is_request(&self) -> bool {
+        self.signature == *b"TPRT"
+    }
+    pub fn is_response(&self) -> bool {
+        self.signature == *b"tprt"
+    }
+    pub fn cmd(&self) -> u32 {
+        self.cmd
+    }
+    /// The size of the data after the header
+    pub fn data_size(&self) -> u32 {
+        self.data_size
+    }
+    /// Always 0.0.0.0 as far as observed
+    pub fn ip_addr(&self) -> Ipv4Addr {
+        Ipv4Addr::from(self.ip_addr)
+    }
+    /// Always 0 as far as observed
+    pub fn token(&self) -> u32 {
+        self.token
+    }
+    /// Parses a packet with the given signature, and returns the header and the data after it.
+    fn parse<'a>(packet: &'a [u8], signature: &[u8; 4]) -> Result<(Self, &'a [u8])> {
+        let header = Self::decode(packet)?;
+        if header.signature() != *signature {
+            return Err(anyhow!(
+                "Invalid signature {:02X?} (expected {:?})",
+                header.signature(),
+                String::from_utf8_lossy(signature)
+            ));
+        }
+        let consts = (header.const00, header.const01, header.const20);
+        if consts != (0x00, 0x01, 0x20) {
+            return Err(anyhow!(
+                "Unexpected constants in the header: {consts:#X?} (expected (0x0, 0x1, 0x20))"
+            ));
+        }
+        let data = &packet[Self::SIZE..];
+        if header.data_size() as usize != data.len() {
+            return Err(anyhow!(
+                "data_size is {} but {} bytes follow the header",
+                header.data_size(),
+                data.len()
+            ));
+        }
+        Ok((header, data))
+    }
+    pub fn parse_request(packet: &[u8]) -> Result<(Self, &[u8])> {
+        Self::parse(packet, b"TPRT").context("Invalid request")
+    }
+    /// Parses a response for req, and returns the header and the data after it.
+    /// An error is returned unless the packet looks like a valid response
+    /// from the printer, so that other devices are not mistaken for one.
+    /// The cmd and the token of the request should be echoed.
+    pub fn parse_response<'a>(packet: &'a [u8], req: &PacketHeader) -> Result<(Self, &'a [u8])> {
+        let cmd = req.cmd();
+        let (header, data) =
+            Self::parse(packet, b"tprt").context(anyhow!("Invalid response for cmd {cmd:#06X}"))?;
+        if header.cmd() != cmd {
+            return Err(anyhow!(
+                "Invalid response for cmd {cmd:#06X}: cmd {:#06X} is echoed",
+                header.cmd()
+            ));
+        }
+        if header.token() != req.token() {
+            return Err(anyhow!(
+                "Invalid response for cmd {cmd:#06X}: token {:#X} is echoed (expected {:#X})",
+                header.token(),
+                req.token()
+            ));
         }
+        Ok((header, data))
+    }
+}
+
+/// Sends a request without data, and returns the validated response.
+fn send_request(
+    socket: &UdpSocket,
+    device_ip: &str,
+    req: &PacketHeader,
+) -> Result<(PacketHeader, Vec<u8>)> {
+    socket
+        .send_to(&req.encode(), printer_addr(device_ip))
+        .context("failed to send")?;
+    let mut buf = [0; 128];
+    let (len, _) = socket.recv_from(&mut buf)?;
+    let (header, data) = PacketHeader::parse_response(&buf[..len], req)?;
+    Ok((header, data.to_vec()))
+}
+
+fn cmd_name(cmd: u32) -> &'static str {
+    match cmd {
+        0x0001 => "status",
+        0x0002 => "start print",
+        0x0003 => "stop print",
+        0x0100 | 0x0101 => "notify data stream",
+        _ => "unknown",
     }
 }
 
-#[repr(packed)]
-#[derive(Copy, Clone)]
+/// Returns a description of a UDP packet, for logging
+pub fn describe_packet(data: &[u8]) -> String {
+    let header = match PacketHeader::decode(data) {
+        Ok(header) => header,
+        Err(_) => return format!("(too short for a header) {data:02X?}"),
+    };
+    format!(
+        "{} cmd {:#06X} ({}), data_size {}, ip_addr {}, token {:#X}, data {:?}",
+        String::from_utf8_lossy(&header.signature()),
+        header.cmd(),
+        cmd_name(header.cmd()),
+        header.data_size(),
+        header.ip_addr(),
+        header.token(),
+        &data[PacketHeader::SIZE..]
+    )
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct StatusRequest {
-    _header: PacketHeader,
+    header: PacketHeader,
+}
+impl Codec for StatusRequest {
+    const SIZE: usize = PacketHeader::SIZE;
+    fn encode_into(&self, buf: &mut Vec<u8>) {
+        self.header.encode_into(buf)
+    }
+    fn decode(data: &[u8]) -> Result<Self> {
+        let (header, _) = PacketHeader::parse_request(data)?;
+        if header.cmd() != Self::CMD {
+            return Err(anyhow!("Not a StatusRequest (cmd {:#06X})", header.cmd()));
+        }
+        Ok(Self { header })
+    }
 }
 impl StatusRequest {
+    pub const CMD: u32 = 1;
     fn new() -> Self {
         Self {
-            _header: PacketHeader::new_request(1, 0),
+            header: PacketHeader::new_request(Self::CMD, 0),
         }
     }
     pub fn send(socket: &UdpSocket, device_ip: &str) -> Result<PrinterStatus> {
-        let req = Self::new();
-        socket
-            .send_to(&req.copy_into_slice(), device_ip.to_string() + ":9100")
-            .context("failed to send")?;
-        let mut buf = [0; 128];
-        let (len, _) = socket.recv_from(&mut buf)?;
-        let res_header = PacketHeader::copy_from_slice(&buf[0..len])?;
-        let data = &buf[size_of::<PacketHeader>()..len];
+        let (res_header, data) = send_request(socket, device_ip, &Self::new().header)?;
         println!("{:?}", data);
+        Self::decode_status(res_header, &data)
+    }
+    /// Decodes the data of a response to the status request
+    pub fn decode_status(res_header: PacketHeader, data: &[u8]) -> Result<PrinterStatus> {
         // idle
         // [20, 0,  0, 4, 0, 0, 0, 0, 64, 0, 0,  0, 0, 0,  0, 0,  0, 0, 0, 0]
         // printing
@@ -101,19 +295,21 @@ impl StatusRequest {
         ))?;
         Ok(match (data[0x01], data[0x0d]) {
             (2, 0) => PrinterStatus::Printing,
-            (0, 0 | 1 | 2) => match data[0x02] {
+            (0, 0..=2) => match data[0x02] {
                 0x06 => PrinterStatus::NoTape,
                 0x21 => PrinterStatus::CoverIsOpened,
-                0x00 => PrinterStatus::SomeTape(match data[0x03] {
-                    0x01 => Tape::W6,
-                    0x02 => Tape::W9,
-                    0x03 => Tape::W12,
-                    0x04 => Tape::W18,
-                    0x05 => Tape::W24,
-                    0x06 => Tape::W36,
-                    0x0B => Tape::W4,
-                    ti => return Err(anyhow!("Unknow tape index {ti:#04X}")),
-                }),
+                0x00 => {
+                    let mut id = [0u8; 7];
+                    id.copy_from_slice(&data[0x04..0x0b]);
+                    let cassette = Cassette {
+                        width_index: data[0x03],
+                        id,
+                    };
+                    match Tape::from_index(cassette.width_index) {
+                        Some(tape) => PrinterStatus::SomeTape(tape, cassette),
+                        None => PrinterStatus::UnknownTape(cassette),
+                    }
+                }
                 _ => PrinterStatus::Unknown(res_header, data),
             },
             (v01, v0d) => {
@@ -124,26 +320,38 @@ impl StatusRequest {
     }
 }
 
-#[repr(packed)]
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct StartPrintRequest {
-    _header: PacketHeader,
+    header: PacketHeader,
+}
+impl Codec for StartPrintRequest {
+    const SIZE: usize = PacketHeader::SIZE;
+    fn encode_into(&self, buf: &mut Vec<u8>) {
+        self.header.encode_into(buf)
+    }
+    fn decode(data: &[u8]) -> Result<Self> {
+        let (header, _) = PacketHeader::parse_request(data)?;
+        if header.cmd() != Self::CMD {
+            return Err(anyhow!(
+                "Not a StartPrintRequest (cmd {:#06X})",
+                header.cmd()
+            ));
+        }
+        Ok(Self { header })
+    }
 }
 impl StartPrintRequest {
+    pub const CMD: u32 = 2;
     fn new() -> Self {
         Self {
-            _header: PacketHeader::new_request(2, 0),
+            header: PacketHeader::new_request(Self::CMD, 0),
         }
     }
     pub fn send(socket: &UdpSocket, device_ip: &str) -> Result<()> {
-        let req = Self::new();
-        socket
-            .send_to(&req.copy_into_slice(), device_ip.to_string() + ":9100")
-            .context("failed to send")?;
-        let mut buf = [0; 128];
-        let (len, _) = socket.recv_from(&mut buf)?;
-        let res_header = PacketHeader::copy_from_slice(&buf[0..len])?;
-        let data = &buf[size_of::<PacketHeader>()..len];
+        let (res_header, data) = send_request(socket, device_ip, &Self::new().header)?;
+        Self::check_response(res_header, &data)
+    }
+    pub fn check_response(res_header: PacketHeader, data: &[u8]) -> Result<()> {
         if data == [2, 0, 0] {
             Ok(())
         } else {
@@ -156,26 +364,38 @@ impl StartPrintRequest {
     }
 }
 
-#[repr(packed)]
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct StopPrintRequest {
-    _header: PacketHeader,
+    header: PacketHeader,
+}
+impl Codec for StopPrintRequest {
+    const SIZE: usize = PacketHeader::SIZE;
+    fn encode_into(&self, buf: &mut Vec<u8>) {
+        self.header.encode_into(buf)
+    }
+    fn decode(data: &[u8]) -> Result<Self> {
+        let (header, _) = PacketHeader::parse_request(data)?;
+        if header.cmd() != Self::CMD {
+            return Err(anyhow!(
+                "Not a StopPrintRequest (cmd {:#06X})",
+                header.cmd()
+            ));
+        }
+        Ok(Self { header })
+    }
 }
 impl StopPrintRequest {
+    pub const CMD: u32 = 3;
     fn new() -> Self {
         Self {
-            _header: PacketHeader::new_request(3, 0),
+            header: PacketHeader::new_request(Self::CMD, 0),
         }
     }
     pub fn send(socket: &UdpSocket, device_ip: &str) -> Result<()> {
-        let req = Self::new();
-        socket
-            .send_to(&req.copy_into_slice(), device_ip.to_string() + ":9100")
-            .context("failed to send")?;
-        let mut buf = [0; 128];
-        let (len, _) = socket.recv_from(&mut buf)?;
-        let res_header = PacketHeader::copy_from_slice(&buf[0..len])?;
-        let data = &buf[size_of::<PacketHeader>()..len];
+        let (res_header, data) = send_request(socket, device_ip, &Self::new().header)?;
+        Self::check_response(res_header, &data)
+    }
+    pub fn check_response(res_header: PacketHeader, data: &[u8]) -> Result<()> {
         if data == [3, 0, 0] {
             Ok(())
         } else {
@@ -188,22 +408,19 @@ impl StopPrintRequest {
     }
 }
 
-pub fn notify_data_stream(socket: &UdpSocket, device_ip: &str) -> Result<()> {
-    let mut buf = [0; 128];
+/// The commands sent in this order after the TCP connection is made
+pub const NOTIFY_DATA_STREAM_CMDS: [u32; 2] = [0x0101, 0x0100];
 
-    let req = PacketHeader::new_request(0x0101, 0);
-    socket
-        .send_to(&req.copy_into_slice(), device_ip.to_string() + ":9100")
-        .context("failed to send")?;
-    socket.recv_from(&mut buf)?;
+pub fn notify_data_stream(socket: &UdpSocket, device_ip: &str) -> Result<()> {
+    let [prepare, notify] = NOTIFY_DATA_STREAM_CMDS;
+    send_request(socket, device_ip, &PacketHeader::new_request(prepare, 0))?;
+    let (res_header, data) =
+        send_request(socket, device_ip, &PacketHeader::new_request(notify, 0))?;
+    check_data_stream_response(res_header, &data)
+}
 
-    let req = PacketHeader::new_request(0x0100, 0);
-    socket
-        .send_to(&req.copy_into_slice(), device_ip.to_string() + ":9100")
-        .context("failed to send")?;
-    let (len, _) = socket.recv_from(&mut buf)?;
-    let res_header = PacketHeader::copy_from_slice(&buf[0..len])?;
-    let data = &buf[size_of::<PacketHeader>()..len];
+/// Checks the response to the last command of NOTIFY_DATA_STREAM_CMDS
+pub fn check_data_stream_response(res_header: PacketHeader, data: &[u8]) -> Result<()> {
     if data == [0x00] {
         println!("Warning: response for cmd 0x0100 was 0x00 (normally 0x10)");
     } else if data != [0x10] {
@@ -215,3 +432,164 @@ pub fn notify_data_stream(socket: &UdpSocket, device_ip: &str) -> Result<()> {
     }
     Ok(())
 }
+
+#[test]
+fn parse_response() {
+    let req = PacketHeader::new_request(3, 0);
+    let mut packet = PacketHeader::new_response(3, 3).encode();
+    packet.extend([3, 0, 0]);
+    let (header, data) = PacketHeader::parse_response(&packet, &req).unwrap();
+    assert!(header.is_response());
+    assert_eq!(
+        (header.cmd(), header.data_size(), header.token()),
+        (3, 3, 0)
+    );
+    assert_eq!(data, [3, 0, 0]);
+    // cmd is not echoed
+    assert!(PacketHeader::parse_response(&packet, &PacketHeader::new_request(2, 0)).is_err());
+    // token is not echoed
+    let other_token = PacketHeader {
+        token: 7,
+        ..PacketHeader::new_response(3, 3)
+    };
+    let mut other = other_token.encode();
+    other.extend([3, 0, 0]);
+    let err = PacketHeader::parse_response(&other, &req).unwrap_err();
+    assert!(err.to_string().contains("token 0x7"), "{err}");
+    // data_size mismatch
+    assert!(PacketHeader::parse_response(&packet[..packet.len() - 1], &req).is_err());
+    // a request is not a response
+    let mut packet = PacketHeader::new_request(3, 3).encode();
+    packet.extend([3, 0, 0]);
+    assert!(PacketHeader::parse_response(&packet, &req).is_err());
+    assert!(PacketHeader::parse_request(&packet).is_ok());
+    // too short
+    assert!(PacketHeader::parse_response(&packet[..16], &req).is_err());
+}
+
+#[test]
+fn trailing_bytes() {
+    let with_trailing_byte = |mut packet: Vec<u8>| {
+        packet.push(0);
+        packet
+    };
+    // ignored by the header, but not by the requests
+    let packet = with_trailing_byte(StatusRequest::new().encode());
+    assert!(PacketHeader::decode(&packet).is_ok());
+    assert!(StatusRequest::decode(&packet).is_err());
+    let packet = with_trailing_byte(StartPrintRequest::new().encode());
+    assert!(StartPrintRequest::decode(&packet).is_err());
+    let packet = with_trailing_byte(StopPrintRequest::new().encode());
+    assert!(StopPrintRequest::decode(&packet).is_err());
+}
+
+#[test]
+fn printer_addrs() {
+    assert_eq!(printer_addr("192.168.1.10"), "192.168.1.10:9100");
+    assert_eq!(printer_addr("127.0.0.1:19100"), "127.0.0.1:19100");
+    assert_eq!(printer_addr("fe80::1"), "[fe80::1]:9100");
+    assert_eq!(printer_addr("[fe80::1]"), "[fe80::1]:9100");
+    assert_eq!(printer_addr("[::1]:19100"), "[::1]:19100");
+    assert_eq!(printer_addr("printer.local"), "printer.local:9100");
+    assert_eq!(printer_addr("printer.local:19100"), "printer.local:19100");
+    assert_eq!(udp_bind_addr("192.168.1.10"), "0.0.0.0:0");
+    assert_eq!(udp_bind_addr("fe80::1"), "[::]:0");
+    assert_eq!(udp_bind_addr("[::1]:19100"), "[::]:0");
+}
+
+#[test]
+fn decode_status() {
+    use crate::TapeKind;
+    let header = PacketHeader::new_response(StatusRequest::CMD, 20);
+    let decode = |data: [u8; 20]| StatusRequest::decode_status(header, &data).unwrap();
+    // the captures in decode_status
+    let idle = [20, 0, 0, 4, 0, 0, 0, 0, 64, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    let PrinterStatus::SomeTape(tape, cassette) = decode(idle) else {
+        panic!("{:?}", decode(idle));
+    };
+    assert_eq!(tape, Tape::W18);
+    assert_eq!(cassette.width_index, 4);
+    assert_eq!(cassette.id, [0, 0, 0, 0, 64, 0, 0]);
+    assert_eq!(cassette.kind(), TapeKind::Standard);
+    let printing = [20, 2, 0, 4, 0, 0, 0, 0, 64, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    assert!(matches!(decode(printing), PrinterStatus::Printing));
+    let completed = [20, 0, 0, 4, 0, 0, 0, 0, 64, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+    assert!(matches!(
+        decode(completed),
+        PrinterStatus::SomeTape(Tape::W18, _)
+    ));
+    let exhausted = [
+        20, 0, 66, 4, 0, 0, 0, 0, 64, 0, 0, 64, 0, 0, 66, 0, 64, 0, 0, 0,
+    ];
+    assert!(matches!(decode(exhausted), PrinterStatus::Unknown(_, _)));
+    let unknown = [
+        20, 0, 0, 4, 0, 0, 0, 0, 64, 0, 0, 0, 0, 0, 66, 0, 64, 0, 0, 0,
+    ];
+    assert!(matches!(
+        decode(unknown),
+        PrinterStatus::SomeTape(Tape::W18, _)
+    ));
+
+    let mut no_tape = idle;
+    no_tape[0x02] = 0x06;
+    assert!(matches!(decode(no_tape), PrinterStatus::NoTape));
+    let mut cover = idle;
+    cover[0x02] = 0x21;
+    assert!(matches!(decode(cover), PrinterStatus::CoverIsOpened));
+    // a width index which is not known yet
+    let mut other = idle;
+    other[0x03] = 0x20;
+    other[0x05] = 0x12;
+    let PrinterStatus::UnknownTape(cassette) = decode(other) else {
+        panic!("{:?}", decode(other));
+    };
+    assert_eq!(cassette.width_index, 0x20);
+    assert_eq!(cassette.id, [0, 0x12, 0, 0, 64, 0, 0]);
+    assert_eq!(cassette.kind(), TapeKind::Unknown);
+    assert!(StatusRequest::decode_status(header, &idle[..19]).is_err());
+}
+
+#[cfg(test)]
+proptest::proptest! {
+    #[test]
+    fn header_round_trip(signature: [u8; 4], fields: [u32; 7]) {
+        let header = PacketHeader {
+            signature,
+            const00: fields[0],
+            const01: fields[1],
+            const20: fields[2],
+            cmd: fields[3],
+            data_size: fields[4],
+            ip_addr: fields[5],
+            token: fields[6],
+        };
+        let bytes = header.encode();
+        proptest::prop_assert_eq!(bytes.len(), PacketHeader::SIZE);
+        proptest::prop_assert_eq!(PacketHeader::decode(&bytes).unwrap(), header);
+    }
+
+    #[test]
+    fn header_bytes_round_trip(bytes: [u8; 32], rest: Vec<u8>) {
+        let packet = [&bytes[..], &rest].concat();
+        let header = PacketHeader::decode(&packet).unwrap();
+        proptest::prop_assert_eq!(header.encode(), bytes.to_vec());
+        // any shorter input is an error, not a panic
+        proptest::prop_assert!(PacketHeader::decode(&bytes[..rest.len() % 32]).is_err());
+    }
+
+    #[test]
+    fn request_round_trip(cmd: u32) {
+        let req = StatusRequest::new();
+        proptest::prop_assert_eq!(StatusRequest::decode(&req.encode()).unwrap(), req);
+        let req = StartPrintRequest::new();
+        proptest::prop_assert_eq!(StartPrintRequest::decode(&req.encode()).unwrap(), req);
+        let req = StopPrintRequest::new();
+        proptest::prop_assert_eq!(StopPrintRequest::decode(&req.encode()).unwrap(), req);
+        // a request of another cmd is not decoded as a StatusRequest
+        let packet = PacketHeader::new_request(cmd, 0).encode();
+        proptest::prop_assert_eq!(
+            StatusRequest::decode(&packet).is_ok(),
+            cmd == StatusRequest::CMD
+        );
+    }
+}
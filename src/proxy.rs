@@ -0,0 +1,287 @@
+use crate::analyzer::analyze_tcp_data_to;
+use crate::protocol::describe_packet;
+use crate::protocol::printer_addr;
+use crate::protocol::udp_bind_addr;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use argh::FromArgs;
+use std::fs;
+use std::fs::File;
+use std::io::Read;
+use std::io::Write;
+use std::net::Shutdown;
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::net::UdpSocket;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// Writes lines prefixed with the seconds since the proxy started, to the log file and stdout.
+#[derive(Clone)]
+struct Logger {
+    start: Instant,
+    file: Arc<Mutex<File>>,
+}
+impl Logger {
+    fn log(&self, msg: &str) {
+        let t = self.start.elapsed().as_secs_f64();
+        let s: String = msg
+            .lines()
+            .map(|line| format!("[{t:10.3}] {line}\n"))
+            .collect();
+        print!("{s}");
+        if let Err(e) = self.file.lock().unwrap().write_all(s.as_bytes()) {
+            eprintln!("Failed to write the log: {e}");
+        }
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Relays UDP commands. Responses from the printer are sent back to the
+/// client that sent the last command.
+fn relay_udp(listen: &str, printer: &str, logger: Logger) -> Result<()> {
+    let downstream = UdpSocket::bind(listen).context(anyhow!("Failed to bind {listen}/udp"))?;
+    let upstream = UdpSocket::bind(udp_bind_addr(printer)).context("failed to bind")?;
+    upstream.connect(printer)?;
+    let client: Arc<Mutex<Option<SocketAddr>>> = Arc::new(Mutex::new(None));
+    {
+        let downstream = downstream.try_clone()?;
+        let upstream = upstream.try_clone()?;
+        let client = client.clone();
+        let logger = logger.clone();
+        thread::spawn(move || {
+            let mut buf = [0; 1500];
+            loop {
+                let len = match upstream.recv(&mut buf) {
+                    Ok(len) => len,
+                    Err(e) => {
+                        logger.log(&format!("UDP printer: {e}"));
+                        continue;
+                    }
+                };
+                let packet = &buf[..len];
+                let Some(to) = *client.lock().unwrap() else {
+                    logger.log(&format!(
+                        "UDP printer -> (nobody): {}",
+                        describe_packet(packet)
+                    ));
+                    continue;
+                };
+                logger.log(&format!("UDP printer -> {to}: {}", describe_packet(packet)));
+                if let Err(e) = downstream.send_to(packet, to) {
+                    logger.log(&format!("UDP failed to send to {to}: {e}"));
+                }
+            }
+        });
+    }
+    let mut buf = [0; 1500];
+    loop {
+        let (len, from) = downstream.recv_from(&mut buf)?;
+        let packet = &buf[..len];
+        *client.lock().unwrap() = Some(from);
+        logger.log(&format!(
+            "UDP {from} -> printer: {}",
+            describe_packet(packet)
+        ));
+        if let Err(e) = upstream.send(packet) {
+            logger.log(&format!("UDP failed to send to the printer: {e}"));
+        }
+    }
+}
+
+/// Relays a TCP connection. The data sent to the printer is dumped and
+/// analyzed when the connection is closed, even if it is closed by an error,
+/// since truncated data is worth capturing as well.
+fn relay_tcp(client: TcpStream, printer: &str, logger: &Logger, dump_dir: &Path) -> Result<()> {
+    let peer = client.peer_addr()?;
+    let upstream = TcpStream::connect(printer)?;
+    logger.log(&format!("TCP {peer} connected"));
+    // The printer is not known to send anything on TCP, but it is relayed just in case.
+    let reverse = {
+        let mut from = upstream.try_clone()?;
+        let mut to = client.try_clone()?;
+        let logger = logger.clone();
+        thread::spawn(move || {
+            let mut buf = [0; 4096];
+            while let Ok(len @ 1..) = from.read(&mut buf) {
+                logger.log(&format!(
+                    "TCP printer -> {peer}: {len} bytes {:02X?}",
+                    &buf[..len]
+                ));
+                if to.write_all(&buf[..len]).is_err() {
+                    break;
+                }
+            }
+            let _ = to.shutdown(Shutdown::Write);
+        })
+    };
+    let mut data = Vec::new();
+    let mut buf = [0; 4096];
+    let (mut from, mut to) = (&client, &upstream);
+    let relayed = loop {
+        let len = match from.read(&mut buf) {
+            Ok(0) => break Ok(()),
+            Ok(len) => len,
+            Err(e) => break Err(anyhow!("Failed to read from {peer}: {e}")),
+        };
+        logger.log(&format!("TCP {peer} -> printer: {len} bytes"));
+        data.extend_from_slice(&buf[..len]);
+        if let Err(e) = to.write_all(&buf[..len]) {
+            break Err(anyhow!("Failed to write to the printer: {e}"));
+        }
+    };
+    let _ = upstream.shutdown(Shutdown::Write);
+    let _ = client.shutdown(Shutdown::Both);
+    let _ = reverse.join();
+    match &relayed {
+        Ok(()) => logger.log(&format!("TCP {peer} closed. {} bytes sent", data.len())),
+        Err(e) => logger.log(&format!(
+            "TCP {peer} closed by an error: {e}. {} bytes received",
+            data.len()
+        )),
+    }
+    fs::create_dir_all(dump_dir).context(anyhow!("Failed to create {dump_dir:?}"))?;
+    let path = dump_dir.join(format!("tcp_{}_{}.bin", unix_time(), peer.port()));
+    fs::write(&path, &data).context(anyhow!("Failed to write {path:?}"))?;
+    logger.log(&format!("TCP data is saved to {path:?}"));
+    let mut analysis = Vec::new();
+    let result = analyze_tcp_data_to(&data, &mut analysis);
+    logger.log(&String::from_utf8_lossy(&analysis));
+    relayed.and(result)
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Relay the traffic between a client (e.g. the vendor software) and the printer, and log it
+#[argh(subcommand, name = "proxy")]
+pub struct ProxyArgs {
+    /// an IPv4 address for the printer (or host:port)
+    #[argh(option)]
+    printer: String,
+    /// address to listen on for both UDP and TCP (default: 0.0.0.0:9100)
+    #[argh(option)]
+    listen: Option<String>,
+    /// path to the log file to append to (default: proxy.log)
+    #[argh(option)]
+    log: Option<String>,
+    /// a directory to save the TCP data to (default: sample_tcp_data)
+    #[argh(option)]
+    dump_dir: Option<String>,
+}
+
+pub fn do_proxy(args: &ProxyArgs) -> Result<()> {
+    let listen = args.listen.as_deref().unwrap_or("0.0.0.0:9100");
+    let printer = printer_addr(&args.printer);
+    let log_path = args.log.as_deref().unwrap_or("proxy.log");
+    let dump_dir = PathBuf::from(args.dump_dir.as_deref().unwrap_or("sample_tcp_data"));
+    let file = File::options()
+        .create(true)
+        .append(true)
+        .open(log_path)
+        .context(anyhow!("Failed to open {log_path}"))?;
+    let logger = Logger {
+        start: Instant::now(),
+        file: Arc::new(Mutex::new(file)),
+    };
+    logger.log(&format!(
+        "Proxy started at {} (unix time): {listen} <-> {printer}",
+        unix_time()
+    ));
+    let listener = TcpListener::bind(listen).context(anyhow!("Failed to listen on {listen}"))?;
+    {
+        let listen = listen.to_string();
+        let printer = printer.clone();
+        let logger = logger.clone();
+        thread::spawn(move || {
+            if let Err(e) = relay_udp(&listen, &printer, logger.clone()) {
+                logger.log(&format!("UDP relay stopped: {e:#}"));
+            }
+        });
+    }
+    for client in listener.incoming() {
+        let client = client?;
+        let printer = printer.clone();
+        let logger = logger.clone();
+        let dump_dir = dump_dir.clone();
+        thread::spawn(move || {
+            if let Err(e) = relay_tcp(client, &printer, &logger, &dump_dir) {
+                logger.log(&format!("TCP relay failed: {e:#}"));
+            }
+        });
+    }
+    Ok(())
+}
+
+#[test]
+fn relay_tcp_to_emulator() {
+    use crate::display::TapeDisplay;
+    use crate::emulator::Emulator;
+    use crate::print::gen_tcp_data;
+    use crate::Tape;
+    use std::time::Duration;
+
+    let emulator = Emulator::start("127.0.0.1:0", Tape::W12, None).unwrap();
+    let printer = emulator.addr().to_string();
+    let dir = std::env::temp_dir().join(format!("sr5900p_proxy_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let logger = Logger {
+        start: Instant::now(),
+        file: Arc::new(Mutex::new(File::create(dir.join("proxy.log")).unwrap())),
+    };
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let data = gen_tcp_data(&TapeDisplay::new(32, 16), &Tape::W12.geometry()).unwrap();
+    let dumps = |n: usize| {
+        let mut dumps: Vec<Vec<u8>> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .filter(|p| p.extension().is_some_and(|e| e == "bin"))
+            .map(|p| fs::read(p).unwrap())
+            .collect();
+        assert_eq!(dumps.len(), n);
+        dumps.sort_by_key(|d| d.len());
+        dumps
+    };
+
+    // a whole job is relayed to the printer and dumped
+    let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (accepted, _) = listener.accept().unwrap();
+    client.write_all(&data).unwrap();
+    client.shutdown(Shutdown::Write).unwrap();
+    relay_tcp(accepted, &printer, &logger, &dir).unwrap();
+    assert_eq!(dumps(1), std::slice::from_ref(&data));
+
+    // a connection reset by the client: what was received is still dumped
+    let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (accepted, _) = listener.accept().unwrap();
+    // closing the client with unread data resets the connection
+    accepted.try_clone().unwrap().write_all(b"x").unwrap();
+    let relay = {
+        let (printer, dir) = (printer.clone(), dir.clone());
+        thread::spawn(move || relay_tcp(accepted, &printer, &logger, &dir))
+    };
+    client.write_all(&data[..20]).unwrap();
+    thread::sleep(Duration::from_millis(200));
+    drop(client);
+    assert!(relay.join().unwrap().is_err());
+    assert_eq!(dumps(2)[0], data[..20]);
+    // both are relayed to the printer
+    while emulator.sessions().len() < 2 {
+        thread::sleep(Duration::from_millis(100));
+    }
+    let mut sessions = emulator.sessions();
+    sessions.sort_by_key(|d| d.len());
+    assert_eq!(sessions, [data[..20].to_vec(), data]);
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,360 @@
+use crate::aztec;
+use crate::datamatrix;
+use crate::display::TapeDisplay;
+use crate::print::px_to_mm;
+use anyhow::anyhow;
+use anyhow::Result;
+use qrcode::types::QrError;
+use qrcode::Color;
+use qrcode::EcLevel;
+use qrcode::QrCode;
+use qrcode::Version;
+use std::str::FromStr;
+
+/// Modules smaller than this (about 0.14 mm) are hard to scan
+const SMALL_MODULE_PX: usize = 2;
+
+/// Error correction level of a QR code
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum QrEcc {
+    L,
+    #[default]
+    M,
+    Q,
+    H,
+}
+impl QrEcc {
+    fn level(&self) -> EcLevel {
+        match self {
+            QrEcc::L => EcLevel::L,
+            QrEcc::M => EcLevel::M,
+            QrEcc::Q => EcLevel::Q,
+            QrEcc::H => EcLevel::H,
+        }
+    }
+}
+impl FromStr for QrEcc {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "l" | "L" => QrEcc::L,
+            "m" | "M" => QrEcc::M,
+            "q" | "Q" => QrEcc::Q,
+            "h" | "H" => QrEcc::H,
+            _ => {
+                return Err(anyhow!(
+                    "Unknown error correction level: {s} (l, m, q or h)"
+                ))
+            }
+        })
+    }
+}
+
+/// The kind of 2D code
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum Symbology {
+    #[default]
+    Qr,
+    DataMatrix,
+    /// Data Matrix in a rectangular size, which fits narrow tapes
+    DataMatrixRect,
+    Aztec,
+}
+impl FromStr for Symbology {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "qr" => Symbology::Qr,
+            "datamatrix" | "dm" => Symbology::DataMatrix,
+            "datamatrix-rect" | "dm-rect" => Symbology::DataMatrixRect,
+            "aztec" => Symbology::Aztec,
+            // Not implemented yet: the encoder needs the codeword tables of
+            // ISO/IEC 15438 (3 clusters of 929 bar/space patterns).
+            "pdf417" => {
+                return Err(anyhow!(
+                    "PDF417 is not implemented yet. Use qr, datamatrix, datamatrix-rect or aztec"
+                ))
+            }
+            _ => {
+                return Err(anyhow!(
+                    "Unknown symbology: {s} (qr, datamatrix, datamatrix-rect or aztec)"
+                ))
+            }
+        })
+    }
+}
+impl Symbology {
+    fn name(&self) -> &'static str {
+        match self {
+            Symbology::Qr => "QR code",
+            Symbology::DataMatrix | Symbology::DataMatrixRect => "Data Matrix",
+            Symbology::Aztec => "Aztec code",
+        }
+    }
+}
+
+/// How to encode and render a 2D code. The default is a QR code, the same as
+/// QrCode::new, with modules as large as fit the tape.
+#[derive(Clone, Debug, Default)]
+pub struct CodeOptions {
+    pub symbology: Symbology,
+    /// ecc, min_version and micro are only for QR codes. It is an error to
+    /// give them for other symbologies.
+    pub ecc: Option<QrEcc>,
+    /// The smallest version to use (1-40, or 1-4 for Micro QR)
+    pub min_version: Option<i16>,
+    /// Use Micro QR, which is smaller for short texts
+    pub micro: bool,
+    /// In modules (default: 4, 2 for Micro QR, 1 for Data Matrix and 0 for Aztec)
+    pub quiet_zone: Option<usize>,
+    /// The size of a module in px (default: as large as fits, and it is an
+    /// error if that is smaller than SMALL_MODULE_PX)
+    pub module_px: Option<usize>,
+}
+impl CodeOptions {
+    /// Encodes the text in the smallest version that is min_version or larger
+    pub fn encode(&self, text: &str) -> Result<QrCode> {
+        let (max_version, version): (i16, fn(i16) -> Version) = if self.micro {
+            (4, Version::Micro)
+        } else {
+            (40, Version::Normal)
+        };
+        let ecc = self.ecc.unwrap_or_default();
+        let min_version = self.min_version.unwrap_or(1);
+        if !(1..=max_version).contains(&min_version) {
+            return Err(anyhow!(
+                "The version should be 1 to {max_version}, but {min_version} is given"
+            ));
+        }
+        for v in min_version..=max_version {
+            match QrCode::with_version(text, version(v), ecc.level()) {
+                Ok(code) => return Ok(code),
+                // Small Micro QR versions support only some levels and characters
+                Err(
+                    QrError::DataTooLong
+                    | QrError::InvalidVersion
+                    | QrError::UnsupportedCharacterSet,
+                ) => continue,
+                Err(e) => return Err(anyhow!("Failed to encode {text:?} as a QR code: {e}")),
+            }
+        }
+        Err(anyhow!(
+            "The text ({} bytes) does not fit a {}QR code with error correction level {:?}",
+            text.len(),
+            if self.micro { "Micro " } else { "" },
+            ecc
+        ))
+    }
+    /// Returns the modules of the code as pixels and the default quiet zone
+    fn modules(&self, text: &str) -> Result<(TapeDisplay, usize)> {
+        if self.symbology != Symbology::Qr
+            && (self.ecc.is_some() || self.min_version.is_some() || self.micro)
+        {
+            return Err(anyhow!(
+                "--qr-ecc, --qr-min-version and --qr-micro are only for QR codes, not for {}",
+                self.symbology.name()
+            ));
+        }
+        Ok(match self.symbology {
+            Symbology::Qr => {
+                let code = self.encode(text)?;
+                let width = code.width();
+                let mut td = TapeDisplay::new(width, width);
+                for (i, color) in code.to_colors().iter().enumerate() {
+                    td.set_pixel(i % width, i / width, *color == Color::Dark);
+                }
+                (td, if self.micro { 2 } else { 4 })
+            }
+            Symbology::DataMatrix => (datamatrix::encode(text, false)?, 1),
+            Symbology::DataMatrixRect => (datamatrix::encode(text, true)?, 1),
+            Symbology::Aztec => (aztec::encode(text)?, 0),
+        })
+    }
+    /// Renders a code of the text at the center of the tape. The label is as
+    /// long as the tape is wide for square codes, and the margins around the
+    /// code are the same on all sides.
+    pub fn render(&self, text: &str, tape_width_px: usize) -> Result<TapeDisplay> {
+        let (modules, default_quiet_zone) = self.modules(text)?;
+        let quiet_zone = self.quiet_zone.unwrap_or(default_quiet_zone);
+        let too_large = || anyhow!("The quiet zone or the module size is too large");
+        let with_quiet_zone = |n: usize| {
+            quiet_zone
+                .checked_mul(2)
+                .and_then(|q| q.checked_add(n))
+                .ok_or_else(too_large)
+        };
+        let (width_in_modules, height_in_modules) = (
+            with_quiet_zone(modules.width)?,
+            with_quiet_zone(modules.height)?,
+        );
+        let module_px = self.module_px.unwrap_or(tape_width_px / height_in_modules);
+        let height = height_in_modules
+            .checked_mul(module_px)
+            .ok_or_else(too_large)?;
+        if module_px == 0 || height > tape_width_px {
+            return Err(anyhow!(
+                "The {} ({width_in_modules}x{height_in_modules} modules including the quiet zone) does not fit the tape ({:.1} mm){}. Try a shorter text, {} or a wider tape",
+                self.symbology.name(),
+                px_to_mm(tape_width_px),
+                match self.module_px {
+                    Some(px) => format!(" with {px} px modules"),
+                    None => String::new(),
+                },
+                match self.symbology {
+                    Symbology::Qr => "a lower --qr-ecc, --qr-micro",
+                    Symbology::DataMatrix => "--symbology datamatrix-rect",
+                    _ => "a smaller --qr-quiet-zone",
+                },
+            ));
+        }
+        if module_px < SMALL_MODULE_PX {
+            // Printed only if the size is given explicitly
+            if self.module_px.is_none() {
+                return Err(anyhow!(
+                    "The modules of the {} would be {module_px} px ({:.2} mm), which is hard to scan. Try a shorter text or a wider tape, or specify --qr-module-px {module_px} to print it anyway",
+                    self.symbology.name(),
+                    px_to_mm(module_px)
+                ));
+            }
+            eprintln!(
+                "Warning: the modules of the {} are {module_px} px ({:.2} mm), which may be hard to scan",
+                self.symbology.name(),
+                px_to_mm(module_px)
+            );
+        }
+        let margin = (tape_width_px - height) / 2;
+        let ofs = margin + quiet_zone * module_px;
+        let mut td = TapeDisplay::new(
+            width_in_modules * module_px + tape_width_px - height,
+            tape_width_px,
+        );
+        for my in 0..modules.height {
+            for mx in 0..modules.width {
+                if !modules.get_pixel(mx, my) {
+                    continue;
+                }
+                for y in 0..module_px {
+                    for x in 0..module_px {
+                        td.set_pixel(ofs + mx * module_px + x, ofs + my * module_px + y, true);
+                    }
+                }
+            }
+        }
+        Ok(td)
+    }
+}
+
+#[test]
+fn code_options() {
+    let opts = CodeOptions::default();
+    // same as QrCode::new with modules as large as fit
+    let td = opts.render("Hello, world!", 144).unwrap();
+    assert_eq!((td.width, td.height), (144, 144));
+    // version 1 (21 modules) + quiet zone: 144 / 29 = 4 px per module
+    let ofs = (144 - 29 * 4) / 2 + 4 * 4;
+    assert!(td.get_pixel(ofs, ofs));
+    assert!(!td.get_pixel(ofs - 1, ofs));
+    assert!(td.get_pixel(ofs + 21 * 4 - 1, ofs));
+    assert!(!td.get_pixel(ofs + 21 * 4, ofs));
+
+    let code = |opts: &CodeOptions| opts.encode("Hello, world!").unwrap();
+    assert_eq!(code(&opts).version(), Version::Normal(1));
+    let opts = CodeOptions {
+        min_version: Some(3),
+        ecc: Some(QrEcc::H),
+        ..Default::default()
+    };
+    assert_eq!(code(&opts).version(), Version::Normal(3));
+    assert_eq!(code(&opts).error_correction_level(), EcLevel::H);
+    let opts = CodeOptions {
+        micro: true,
+        ecc: Some(QrEcc::L),
+        ..Default::default()
+    };
+    assert!(matches!(code(&opts).version(), Version::Micro(_)));
+
+    // errors instead of panics
+    assert!(CodeOptions::default().encode(&"x".repeat(4000)).is_err());
+    assert!(CodeOptions::default()
+        .render(&"x".repeat(2000), 144)
+        .is_err());
+    let opts = CodeOptions {
+        module_px: Some(5),
+        ..Default::default()
+    };
+    assert!(opts.render("Hello, world!", 144).is_err());
+    let opts = CodeOptions {
+        quiet_zone: Some(0),
+        module_px: Some(6),
+        ..Default::default()
+    };
+    assert!(opts.render("Hello, world!", 144).is_ok());
+    // 1 px modules (29 modules on a 4 mm tape) only if they are asked for
+    assert!(CodeOptions::default().render("Hello, world!", 40).is_err());
+    let opts = CodeOptions {
+        module_px: Some(1),
+        ..Default::default()
+    };
+    assert!(opts.render("Hello, world!", 40).is_ok());
+    // no overflow with huge sizes
+    for (quiet_zone, module_px) in [(Some(usize::MAX), None), (None, Some(usize::MAX))] {
+        let opts = CodeOptions {
+            quiet_zone,
+            module_px,
+            ..Default::default()
+        };
+        assert!(opts.render("Hello, world!", 144).is_err());
+    }
+
+    // other symbologies in integer module sizes
+    for symbology in [
+        Symbology::DataMatrix,
+        Symbology::DataMatrixRect,
+        Symbology::Aztec,
+    ] {
+        let opts = CodeOptions {
+            symbology,
+            ..Default::default()
+        };
+        let (modules, quiet_zone) = opts.modules("SRV-0001").unwrap();
+        let td = opts.render("SRV-0001", 72).unwrap();
+        assert_eq!(td.height, 72);
+        let module_px = 72 / (modules.height + 2 * quiet_zone);
+        let ofs = (72 - (modules.height + 2 * quiet_zone) * module_px) / 2 + quiet_zone * module_px;
+        for my in 0..modules.height {
+            for mx in 0..modules.width {
+                let (x, y) = (ofs + mx * module_px, ofs + my * module_px);
+                let dark = modules.get_pixel(mx, my);
+                assert_eq!(td.get_pixel(x, y), dark);
+                assert_eq!(td.get_pixel(x + module_px - 1, y + module_px - 1), dark);
+            }
+        }
+        // the options of QR codes are not ignored silently
+        for opts in [
+            CodeOptions {
+                ecc: Some(QrEcc::M),
+                ..opts.clone()
+            },
+            CodeOptions {
+                min_version: Some(1),
+                ..opts.clone()
+            },
+            CodeOptions {
+                micro: true,
+                ..opts
+            },
+        ] {
+            assert!(opts.render("SRV-0001", 72).is_err());
+        }
+    }
+    // rectangular Data Matrix fits tapes where square ones do not
+    let opts = |symbology| CodeOptions {
+        symbology,
+        module_px: Some(3),
+        ..Default::default()
+    };
+    let text = "P/N 1234-5678";
+    assert!(opts(Symbology::DataMatrix).render(text, 48).is_err());
+    let td = opts(Symbology::DataMatrixRect).render(text, 48).unwrap();
+    assert!(td.width > td.height);
+}
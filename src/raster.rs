@@ -0,0 +1,367 @@
+use crate::display::TapeDisplay;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+/// The sync word of a PWG raster stream (PWG 5102.4)
+const PWG_SYNC_WORD: &[u8] = b"RaS2";
+const PWG_HEADER_SIZE: usize = 1796;
+
+/// Pixels darker than this (0-255) are printed
+const THRESHOLD: u8 = 128;
+
+/// The size of a page is given by the data, so larger pages are rejected
+/// before they are allocated. A page is at most as wide as the widest tape
+/// (36 mm) and 1 m long, at 600 DPI.
+const MAX_PAGE_SHORT_SIDE_PX: u64 = 851;
+const MAX_PAGE_LONG_SIDE_PX: u64 = 23622;
+/// The largest job accepted on a socket
+pub const MAX_JOB_BYTES: u64 = 64 << 20;
+
+fn check_page_size(width: u64, height: u64) -> Result<()> {
+    if std::cmp::min(width, height) > MAX_PAGE_SHORT_SIDE_PX
+        || std::cmp::max(width, height) > MAX_PAGE_LONG_SIDE_PX
+    {
+        return Err(anyhow!(
+            "The page ({width}x{height} px) is too large for a label (at most {MAX_PAGE_SHORT_SIDE_PX}x{MAX_PAGE_LONG_SIDE_PX} px)"
+        ));
+    }
+    Ok(())
+}
+
+// color spaces (cups_cspace_t)
+const CSPACE_W: u32 = 0;
+const CSPACE_RGB: u32 = 1;
+const CSPACE_K: u32 = 3;
+const CSPACE_SW: u32 = 18;
+const CSPACE_SRGB: u32 = 19;
+
+/// The (color space, bits per color, bits per pixel) that can be decoded.
+/// The others are rejected with the header, so at most 3 bytes per pixel
+/// are allocated for a line.
+const SUPPORTED_FORMATS: &[(u32, u32, u32)] = &[
+    (CSPACE_W, 1, 1),
+    (CSPACE_SW, 1, 1),
+    (CSPACE_K, 1, 1),
+    (CSPACE_W, 8, 8),
+    (CSPACE_SW, 8, 8),
+    (CSPACE_K, 8, 8),
+    (CSPACE_RGB, 8, 24),
+    (CSPACE_SRGB, 8, 24),
+];
+
+/// Decodes a PNG image. Dark pixels will be printed.
+pub fn decode_png(data: &[u8]) -> Result<TapeDisplay> {
+    // the size in the IHDR chunk, which is always the first one
+    if let Some(ihdr) = data.get(16..24) {
+        check_page_size(
+            u32::from_be_bytes(ihdr[..4].try_into()?) as u64,
+            u32::from_be_bytes(ihdr[4..].try_into()?) as u64,
+        )?;
+    }
+    let image = image::load_from_memory(data)
+        .context("Failed to decode the image")?
+        .to_luma8();
+    let mut td = TapeDisplay::new(image.width() as usize, image.height() as usize);
+    for (x, y, p) in image.enumerate_pixels() {
+        td.set_pixel(x as usize, y as usize, p.0[0] < THRESHOLD);
+    }
+    Ok(td)
+}
+
+/// Fields of a PWG raster page header that are needed to decode the page.
+/// All the fields are big-endian u32 at the offsets in cups_page_header2_t.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PwgPageHeader {
+    pub resolution: (u32, u32),
+    pub width: u32,
+    pub height: u32,
+    pub bits_per_color: u32,
+    pub bits_per_pixel: u32,
+    pub bytes_per_line: u32,
+    pub color_space: u32,
+}
+impl PwgPageHeader {
+    fn parse(header: &[u8]) -> Result<Self> {
+        let u32_at = |ofs: usize| -> Result<u32> {
+            Ok(u32::from_be_bytes(
+                header
+                    .get(ofs..ofs + 4)
+                    .context("PWG raster header is too short")?
+                    .try_into()?,
+            ))
+        };
+        let header = Self {
+            resolution: (u32_at(276)?, u32_at(280)?),
+            width: u32_at(372)?,
+            height: u32_at(376)?,
+            bits_per_color: u32_at(384)?,
+            bits_per_pixel: u32_at(388)?,
+            bytes_per_line: u32_at(392)?,
+            color_space: u32_at(400)?,
+        };
+        let format = (
+            header.color_space,
+            header.bits_per_color,
+            header.bits_per_pixel,
+        );
+        if !SUPPORTED_FORMATS.contains(&format) {
+            return Err(anyhow!(
+                "Unsupported PWG raster format: color space {}, {} bits per color, {} bits per pixel",
+                format.0,
+                format.1,
+                format.2
+            ));
+        }
+        check_page_size(header.width as u64, header.height as u64)?;
+        // the lines are not padded (PWG 5102.4), so a line is bounded by the
+        // page size and the format checked above
+        if header.bytes_per_line as u64
+            != (header.width as u64 * header.bits_per_pixel as u64).div_ceil(8)
+        {
+            return Err(anyhow!("Inconsistent PWG raster header: {header:?}"));
+        }
+        Ok(header)
+    }
+    /// Bytes of a unit in the run-length encoding
+    fn bytes_per_unit(&self) -> usize {
+        std::cmp::max(self.bits_per_pixel as usize / 8, 1)
+    }
+    /// Returns whether the pixel at x in a decoded line should be printed
+    fn is_ink(&self, line: &[u8], x: usize) -> Result<bool> {
+        Ok(match (self.color_space, self.bits_per_color, self.bits_per_pixel) {
+            (CSPACE_W | CSPACE_SW | CSPACE_K, 1, 1) => {
+                let bit = line[x / 8] >> (7 - x % 8) & 1;
+                // 1 is white for W and black for K
+                (bit == 1) == (self.color_space == CSPACE_K)
+            }
+            (CSPACE_W | CSPACE_SW, 8, 8) => line[x] < THRESHOLD,
+            (CSPACE_K, 8, 8) => line[x] >= THRESHOLD,
+            (CSPACE_RGB | CSPACE_SRGB, 8, 24) => {
+                let p = &line[x * 3..x * 3 + 3];
+                let luma = (p[0] as u32 * 299 + p[1] as u32 * 587 + p[2] as u32 * 114) / 1000;
+                luma < THRESHOLD as u32
+            }
+            _ => {
+                return Err(anyhow!(
+                    "Unsupported PWG raster format: color space {}, {} bits per color, {} bits per pixel",
+                    self.color_space,
+                    self.bits_per_color,
+                    self.bits_per_pixel
+                ))
+            }
+        })
+    }
+    /// The value to fill the rest of a line with (run-length code 128)
+    fn white(&self) -> u8 {
+        if self.color_space == CSPACE_K {
+            0x00
+        } else {
+            0xff
+        }
+    }
+}
+
+/// Decodes a PWG raster stream into pages.
+pub fn decode_pwg_raster(data: &[u8]) -> Result<Vec<TapeDisplay>> {
+    let mut data = data
+        .strip_prefix(PWG_SYNC_WORD)
+        .context("Not a PWG raster stream")?;
+    let mut pages = Vec::new();
+    while !data.is_empty() {
+        let header = PwgPageHeader::parse(data.get(..PWG_HEADER_SIZE).context(anyhow!(
+            "PWG raster header of page {} is too short",
+            pages.len() + 1
+        ))?)?;
+        data = &data[PWG_HEADER_SIZE..];
+        let (td, rest) = decode_pwg_page(&header, data)
+            .context(anyhow!("Failed to decode page {}", pages.len() + 1))?;
+        pages.push(td);
+        data = rest;
+    }
+    Ok(pages)
+}
+
+fn decode_pwg_page<'a>(header: &PwgPageHeader, data: &'a [u8]) -> Result<(TapeDisplay, &'a [u8])> {
+    // A line takes 2 bytes at least (the repeat count and a fill of the
+    // whole line), and it can be repeated 256 times.
+    if (header.height as usize).div_ceil(256) * 2 > data.len() {
+        return Err(anyhow!(
+            "The data ({} bytes) is too short for {} lines",
+            data.len(),
+            header.height
+        ));
+    }
+    let mut td = TapeDisplay::new(header.width as usize, header.height as usize);
+    let bytes_per_line = header.bytes_per_line as usize;
+    let unit = header.bytes_per_unit();
+    let mut pos = 0;
+    let mut next = || {
+        let v = data.get(pos).copied().context("Unexpected end of the data");
+        pos += 1;
+        v
+    };
+    let mut y = 0;
+    while y < td.height {
+        // the line is repeated (1 + this value) times
+        let repeat = next()? as usize + 1;
+        let mut line = Vec::with_capacity(bytes_per_line);
+        while line.len() < bytes_per_line {
+            match next()? {
+                128 => line.resize(bytes_per_line, header.white()),
+                n @ 0..=127 => {
+                    let mut v = Vec::with_capacity(unit);
+                    for _ in 0..unit {
+                        v.push(next()?);
+                    }
+                    for _ in 0..n as usize + 1 {
+                        line.extend(&v);
+                    }
+                }
+                n => {
+                    for _ in 0..(257 - n as usize) * unit {
+                        line.push(next()?);
+                    }
+                }
+            }
+        }
+        if line.len() != bytes_per_line {
+            return Err(anyhow!("Line {y} is longer than {bytes_per_line} bytes"));
+        }
+        for _ in 0..repeat {
+            for x in 0..td.width {
+                td.set_pixel(x, y, header.is_ink(&line, x)?);
+            }
+            y += 1;
+        }
+    }
+    Ok((td, &data[pos..]))
+}
+
+/// Decodes a PNG image or a PWG raster stream into pages.
+pub fn decode_pages(data: &[u8]) -> Result<Vec<TapeDisplay>> {
+    if data.starts_with(PNG_SIGNATURE) {
+        Ok(vec![decode_png(data)?])
+    } else if data.starts_with(PWG_SYNC_WORD) {
+        decode_pwg_raster(data)
+    } else {
+        Err(anyhow!(
+            "Unknown data format (expected PNG or PWG raster): {:02X?}",
+            &data[..std::cmp::min(data.len(), 8)]
+        ))
+    }
+}
+
+/// Returns the bounding box of the content as (x, y, width, height)
+fn content_box(td: &TapeDisplay) -> Option<(usize, usize, usize, usize)> {
+    let ys: Vec<usize> = (0..td.height)
+        .filter(|y| td.framebuffer[*y].iter().any(|p| *p))
+        .collect();
+    let xs: Vec<usize> = (0..td.width)
+        .filter(|x| (0..td.height).any(|y| td.get_pixel(*x, y)))
+        .collect();
+    Some((
+        *xs.first()?,
+        *ys.first()?,
+        xs.last()? - xs.first()? + 1,
+        ys.last()? - ys.first()? + 1,
+    ))
+}
+
+/// Scales a page to the printable width of the tape, keeping the aspect ratio.
+/// Pages taller than wide are rotated so that the long side runs along the tape.
+/// If trim is true, blank margins around the content are removed beforehand.
+pub fn fit_to_tape(page: &TapeDisplay, tape_width_px: usize, trim: bool) -> TapeDisplay {
+    let page = if page.height > page.width {
+        page.rotated()
+    } else {
+        page.clone()
+    };
+    let page = match content_box(&page) {
+        Some((x, y, w, h)) if trim => {
+            let mut cropped = TapeDisplay::new(w, h);
+            for cy in 0..h {
+                for cx in 0..w {
+                    cropped.set_pixel(cx, cy, page.get_pixel(x + cx, y + cy));
+                }
+            }
+            cropped
+        }
+        _ => page,
+    };
+    if page.height == 0 {
+        return TapeDisplay::new(0, tape_width_px);
+    }
+    let width = page.width * tape_width_px / page.height;
+    page.resized(width, tape_width_px)
+}
+
+#[test]
+fn pwg_raster() {
+    // a 16x3 page in 1-bit black
+    let mut header = vec![0u8; PWG_HEADER_SIZE];
+    for (ofs, v) in [
+        (276, 360),
+        (280, 360),
+        (372, 16),
+        (376, 3),
+        (384, 1),
+        (388, 1),
+        (392, 2),
+        (400, 3),
+    ] {
+        header[ofs..ofs + 4].copy_from_slice(&(v as u32).to_be_bytes());
+    }
+    let mut data = PWG_SYNC_WORD.to_vec();
+    data.extend(&header);
+    // line 0 repeated twice: 0xF0 x 2 (a run)
+    data.extend([1, 1, 0xf0]);
+    // line 1: 0x01, 0x80 (literal)
+    data.extend([0, 255, 0x01, 0x80]);
+    let pages = decode_pwg_raster(&data).unwrap();
+    assert_eq!(pages.len(), 1);
+    let td = &pages[0];
+    assert_eq!((td.width, td.height), (16, 3));
+    for y in 0..2 {
+        let row: Vec<bool> = (0..16).map(|x| td.get_pixel(x, y)).collect();
+        assert_eq!(row, (0..16).map(|x| x % 8 < 4).collect::<Vec<_>>());
+    }
+    let row: Vec<bool> = (0..16).map(|x| td.get_pixel(x, 2)).collect();
+    assert_eq!(row, (0..16).map(|x| x == 7 || x == 8).collect::<Vec<_>>());
+    // truncated data
+    assert!(decode_pwg_raster(&data[..data.len() - 1]).is_err());
+    // a small header claiming a huge page is rejected before allocating it
+    let mut huge = data.clone();
+    for (ofs, v) in [
+        (372, 4_000_000_000u32),
+        (376, 4_000_000_000),
+        (392, 500_000_000),
+    ] {
+        let ofs = PWG_SYNC_WORD.len() + ofs;
+        huge[ofs..ofs + 4].copy_from_slice(&v.to_be_bytes());
+    }
+    assert!(decode_pwg_raster(&huge).is_err());
+    // so is a huge line in an unsupported format
+    let mut huge_line = data.clone();
+    for (ofs, v) in [(388, 0x8000_0000u32), (392, 0x100_0000)] {
+        let ofs = PWG_SYNC_WORD.len() + ofs;
+        huge_line[ofs..ofs + 4].copy_from_slice(&v.to_be_bytes());
+    }
+    let err = decode_pwg_raster(&huge_line).err().unwrap();
+    assert!(err.to_string().contains("Unsupported"), "{err}");
+    // a long page with too little data for its lines
+    let mut long = data.clone();
+    long[PWG_SYNC_WORD.len() + 376..][..4].copy_from_slice(&20000u32.to_be_bytes());
+    assert!(decode_pwg_raster(&long).is_err());
+    let mut png = PNG_SIGNATURE.to_vec();
+    png.extend([0, 0, 0, 13]);
+    png.extend(b"IHDR");
+    png.extend(4_000_000_000u32.to_be_bytes());
+    png.extend(1u32.to_be_bytes());
+    let err = decode_pages(&png).err().unwrap();
+    assert!(err.to_string().contains("too large"), "{err}");
+
+    let fit = fit_to_tape(td, 6, false);
+    assert_eq!((fit.width, fit.height), (32, 6));
+}
@@ -0,0 +1,66 @@
+/// Arithmetic in GF(2^bits), for the error correction of 2D codes
+pub struct Galois {
+    exp: Vec<u16>,
+    log: Vec<u16>,
+}
+impl Galois {
+    /// poly is the primitive polynomial, e.g. 0x12D for Data Matrix
+    pub fn new(bits: u32, poly: u32) -> Self {
+        let size = 1usize << bits;
+        let mut exp = vec![0; size * 2];
+        let mut log = vec![0; size];
+        let mut x = 1u32;
+        for (i, e) in exp.iter_mut().take(size - 1).enumerate() {
+            *e = x as u16;
+            log[x as usize] = i as u16;
+            x <<= 1;
+            if x as usize & size != 0 {
+                x ^= poly;
+            }
+        }
+        for i in size - 1..size * 2 {
+            exp[i] = exp[i - (size - 1)];
+        }
+        Self { exp, log }
+    }
+    fn mul(&self, a: u16, b: u16) -> u16 {
+        if a == 0 || b == 0 {
+            0
+        } else {
+            self.exp[self.log[a as usize] as usize + self.log[b as usize] as usize]
+        }
+    }
+    /// Returns n Reed-Solomon check words for data. The roots of the
+    /// generator polynomial are a^1 to a^n, as in Data Matrix and Aztec.
+    pub fn check_words(&self, data: &[u16], n: usize) -> Vec<u16> {
+        // coefficients of the generator, from the highest degree
+        let mut g = vec![1u16];
+        for i in 1..=n {
+            let mut next = vec![0; g.len() + 1];
+            for (j, c) in g.iter().enumerate() {
+                next[j] ^= c;
+                next[j + 1] ^= self.mul(*c, self.exp[i]);
+            }
+            g = next;
+        }
+        let mut rem = vec![0u16; n];
+        for d in data {
+            let f = d ^ rem[0];
+            rem.rotate_left(1);
+            rem[n - 1] = 0;
+            for (r, c) in rem.iter_mut().zip(&g[1..]) {
+                *r ^= self.mul(*c, f);
+            }
+        }
+        rem
+    }
+    /// Returns whether words (data followed by n check words) have no errors
+    pub fn is_valid(&self, words: &[u16], n: usize) -> bool {
+        (1..=n).all(|i| {
+            words
+                .iter()
+                .fold(0, |acc, w| self.mul(acc, self.exp[i]) ^ w)
+                == 0
+        })
+    }
+}
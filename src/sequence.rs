@@ -0,0 +1,193 @@
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CheckDigit {
+    /// Luhn algorithm (mod 10, weights 2-1 from the right)
+    Luhn,
+    /// GS1 check digit (mod 10, weights 3-1 from the right)
+    Gs1,
+}
+impl CheckDigit {
+    pub fn calc(&self, digits: &str) -> Result<char> {
+        let digits: Vec<u32> = digits
+            .chars()
+            .map(|c| c.to_digit(10).context(anyhow!("Not a digit: {c}")))
+            .collect::<Result<_>>()?;
+        let weights: [u32; 2] = match self {
+            CheckDigit::Luhn => [2, 1],
+            CheckDigit::Gs1 => [3, 1],
+        };
+        let sum: u32 = digits
+            .iter()
+            .rev()
+            .enumerate()
+            .map(|(i, d)| {
+                let v = d * weights[i % 2];
+                match self {
+                    // digits of the doubled value are summed up
+                    CheckDigit::Luhn => v / 10 + v % 10,
+                    CheckDigit::Gs1 => v,
+                }
+            })
+            .sum();
+        Ok(char::from_digit((10 - sum % 10) % 10, 10).unwrap_or('0'))
+    }
+}
+impl FromStr for CheckDigit {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "luhn" => CheckDigit::Luhn,
+            "gs1" => CheckDigit::Gs1,
+            _ => return Err(anyhow!("Unknown check digit: {s} (luhn or gs1)")),
+        })
+    }
+}
+
+/// A format of labels like "SRV-{:04}". "{}" is replaced with the number,
+/// and "{:0N}" with the number zero-padded to N digits.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SequenceFormat {
+    prefix: String,
+    width: usize,
+    suffix: String,
+}
+impl SequenceFormat {
+    pub fn format(&self, n: u64, check: Option<CheckDigit>) -> Result<String> {
+        let mut num = format!("{n:0width$}", width = self.width);
+        if let Some(check) = check {
+            let c = check.calc(&num)?;
+            num.push(c);
+        }
+        Ok(format!("{}{num}{}", self.prefix, self.suffix))
+    }
+}
+impl FromStr for SequenceFormat {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        let begin = s.find('{').context(anyhow!(
+            "No placeholder in the format: {s} (e.g. SRV-{{:04}})"
+        ))?;
+        let end = begin
+            + s[begin..]
+                .find('}')
+                .context(anyhow!("Unterminated placeholder in the format: {s}"))?;
+        let spec = &s[begin + 1..end];
+        let width = match spec {
+            "" => 0,
+            _ => spec
+                .strip_prefix(":0")
+                .or_else(|| spec.strip_prefix(':'))
+                .and_then(|w| w.parse().ok())
+                .context(anyhow!("Invalid placeholder in the format: {{{spec}}}"))?,
+        };
+        let suffix = &s[end + 1..];
+        if suffix.contains('{') {
+            return Err(anyhow!(
+                "Only one placeholder is allowed in the format: {s}"
+            ));
+        }
+        Ok(Self {
+            prefix: s[..begin].to_string(),
+            width,
+            suffix: suffix.to_string(),
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Sequence {
+    pub format: SequenceFormat,
+    pub start: u64,
+    pub count: u64,
+    pub step: u64,
+    pub check: Option<CheckDigit>,
+}
+impl Sequence {
+    /// Returns the numbers in the sequence from next (inclusive)
+    pub fn numbers_from(&self, next: u64) -> Result<impl Iterator<Item = u64>> {
+        if self.step == 0 {
+            return Err(anyhow!("The step of a sequence should be 1 or larger"));
+        }
+        let end = self
+            .count
+            .checked_mul(self.step)
+            .and_then(|len| len.checked_add(self.start))
+            .context(anyhow!(
+                "The sequence is too long (start: {}, count: {}, step: {})",
+                self.start,
+                self.count,
+                self.step
+            ))?;
+        if next < self.start || next > end || !(next - self.start).is_multiple_of(self.step) {
+            return Err(anyhow!(
+                "{next} is not in the sequence (start: {}, count: {}, step: {})",
+                self.start,
+                self.count,
+                self.step
+            ));
+        }
+        Ok((next..end).step_by(self.step as usize))
+    }
+    pub fn label(&self, n: u64) -> Result<String> {
+        self.format.format(n, self.check)
+    }
+}
+
+/// Returns the next number recorded in the state file, if it exists
+pub fn load_next_number(path: &Path) -> Result<Option<u64>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let s = fs::read_to_string(path).context(anyhow!("Failed to read {path:?}"))?;
+    Ok(Some(
+        s.trim()
+            .parse()
+            .context(anyhow!("Invalid state file {path:?}: {s}"))?,
+    ))
+}
+
+pub fn save_next_number(path: &Path, next: u64) -> Result<()> {
+    // write and rename, so that the state is not lost if interrupted
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, format!("{next}\n")).context(anyhow!("Failed to write {tmp:?}"))?;
+    fs::rename(&tmp, path).context(anyhow!("Failed to update {path:?}"))
+}
+
+#[test]
+fn sequence_format() {
+    let f: SequenceFormat = "SRV-{:04}".parse().unwrap();
+    assert_eq!(f.format(1, None).unwrap(), "SRV-0001");
+    assert_eq!(f.format(12345, None).unwrap(), "SRV-12345");
+    let f: SequenceFormat = "{}-A".parse().unwrap();
+    assert_eq!(f.format(7, None).unwrap(), "7-A");
+    assert!("SRV".parse::<SequenceFormat>().is_err());
+    assert!("{:x}".parse::<SequenceFormat>().is_err());
+    // check digits
+    assert_eq!(CheckDigit::Luhn.calc("7992739871").unwrap(), '3');
+    assert_eq!(CheckDigit::Gs1.calc("629104150021").unwrap(), '3');
+    let f: SequenceFormat = "SRV-{:04}".parse().unwrap();
+    assert_eq!(f.format(1, Some(CheckDigit::Luhn)).unwrap(), "SRV-00018");
+    // numbers
+    let seq = |start, count, step| Sequence {
+        format: f.clone(),
+        start,
+        count,
+        step,
+        check: None,
+    };
+    let numbers = |seq: Sequence, next| seq.numbers_from(next).map(|n| n.collect::<Vec<_>>());
+    assert_eq!(numbers(seq(1, 3, 2), 1).unwrap(), [1, 3, 5]);
+    assert_eq!(numbers(seq(1, 3, 2), 5).unwrap(), [5]);
+    assert!(numbers(seq(1, 3, 2), 7).unwrap().is_empty());
+    assert!(numbers(seq(1, 3, 2), 4).is_err());
+    assert!(numbers(seq(1, 3, 0), 1).is_err());
+    // too long, instead of an overflow
+    assert!(numbers(seq(1, u64::MAX, 1), 1).is_err());
+    assert!(numbers(seq(u64::MAX, 1, 1), u64::MAX).is_err());
+}
@@ -0,0 +1,511 @@
+use crate::display::TapeDisplay;
+use crate::encoder::JobEncoder;
+use crate::layout::LengthOptions;
+use crate::print::detect_geometry;
+use crate::print::gen_text_td;
+use crate::print::render_qr_text;
+use crate::print::PrintSession;
+use crate::qr::CodeOptions;
+use crate::raster::decode_png;
+use crate::raster::MAX_JOB_BYTES;
+use crate::PrinterStatus;
+use crate::TapeKind;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use argh::FromArgs;
+use serde::Deserialize;
+use serde::Serialize;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::thread;
+use tiny_http::Header;
+use tiny_http::Method;
+use tiny_http::Request;
+use tiny_http::Response;
+use tiny_http::Server;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Queued,
+    Printing,
+    Done,
+    Failed,
+    Canceled,
+}
+
+/// What to print, submitted as JSON to POST /jobs.
+/// Exactly one of qr_text and text should be given. Lengths are strings
+/// as in the command line options, e.g. {"text": "hello", "min_length": "30mm"}.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JobRequest {
+    pub qr_text: Option<String>,
+    pub text: Option<String>,
+    pub length: Option<String>,
+    pub min_length: Option<String>,
+    pub margin_left: Option<String>,
+    pub margin_right: Option<String>,
+    pub align: Option<String>,
+}
+
+#[derive(Clone)]
+enum Content {
+    QrText(String),
+    Text(String),
+    /// An image posted as image/png. Dark pixels will be printed.
+    Image(TapeDisplay),
+}
+impl Content {
+    fn render(&self, tape_width_px: usize) -> Result<TapeDisplay> {
+        match self {
+            Content::QrText(text) => render_qr_text(text, tape_width_px, &CodeOptions::default()),
+            Content::Text(text) => {
+                let text_td = gen_text_td(text, tape_width_px)?;
+                let mut td = TapeDisplay::new(text_td.width, tape_width_px);
+                td.overlay_or(
+                    &text_td,
+                    0,
+                    tape_width_px.saturating_sub(text_td.height) / 2,
+                );
+                Ok(td)
+            }
+            Content::Image(image) => {
+                if image.height > tape_width_px {
+                    return Err(anyhow!(
+                        "The image ({} px) is taller than the printable width of the tape ({} px)",
+                        image.height,
+                        tape_width_px
+                    ));
+                }
+                let mut td = TapeDisplay::new(image.width, tape_width_px);
+                td.overlay_or(image, 0, (tape_width_px - image.height) / 2);
+                Ok(td)
+            }
+        }
+    }
+    fn summary(&self) -> String {
+        match self {
+            Content::QrText(text) => format!("qr_text: {text}"),
+            Content::Text(text) => format!("text: {text}"),
+            Content::Image(td) => format!("image: {}x{}", td.width, td.height),
+        }
+    }
+}
+
+/// The number of finished (done, failed or canceled) jobs kept to be listed.
+/// Older ones are forgotten.
+const MAX_FINISHED_JOBS: usize = 100;
+
+struct Job {
+    id: u64,
+    state: JobState,
+    summary: String,
+    /// Taken by the worker when the job is printed, or dropped when it is canceled
+    content: Option<Content>,
+    length: LengthOptions,
+    error: Option<String>,
+}
+
+/// The state of a job, returned by the API
+#[derive(Clone, Debug, Serialize)]
+pub struct JobInfo {
+    pub id: u64,
+    pub state: JobState,
+    pub summary: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+impl From<&Job> for JobInfo {
+    fn from(job: &Job) -> Self {
+        Self {
+            id: job.id,
+            state: job.state,
+            summary: job.summary.clone(),
+            error: job.error.clone(),
+        }
+    }
+}
+
+/// Jobs are kept in the order of submission, and processed one by one
+/// by the worker so that only one print session is open at a time.
+#[derive(Default)]
+struct JobQueue {
+    jobs: Mutex<Vec<Job>>,
+    cond: Condvar,
+    last_id: AtomicU64,
+}
+impl JobQueue {
+    fn submit(&self, content: Content, length: LengthOptions) -> JobInfo {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = Job {
+            id: self.last_id.fetch_add(1, Ordering::Relaxed) + 1,
+            state: JobState::Queued,
+            summary: content.summary(),
+            content: Some(content),
+            length,
+            error: None,
+        };
+        let info = JobInfo::from(&job);
+        jobs.push(job);
+        self.cond.notify_one();
+        info
+    }
+    fn list(&self) -> Vec<JobInfo> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .iter()
+            .map(JobInfo::from)
+            .collect()
+    }
+    fn get(&self, id: u64) -> Option<JobInfo> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|j| j.id == id)
+            .map(JobInfo::from)
+    }
+    /// Cancels a queued job. Returns Err with the current state if it can not be canceled.
+    fn cancel(&self, id: u64) -> Option<Result<JobInfo, JobState>> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = jobs.iter_mut().find(|j| j.id == id)?;
+        if job.state != JobState::Queued {
+            return Some(Err(job.state));
+        }
+        job.state = JobState::Canceled;
+        job.content = None;
+        let info = JobInfo::from(&*job);
+        forget_finished_jobs(&mut jobs);
+        Some(Ok(info))
+    }
+    /// Waits for a queued job and marks it as printing.
+    fn next_job(&self) -> (u64, Content, LengthOptions) {
+        let mut jobs = self.jobs.lock().unwrap();
+        loop {
+            if let Some(job) = jobs.iter_mut().find(|j| j.state == JobState::Queued) {
+                job.state = JobState::Printing;
+                if let Some(content) = job.content.take() {
+                    return (job.id, content, job.length.clone());
+                }
+            }
+            jobs = self.cond.wait(jobs).unwrap();
+        }
+    }
+    fn finish(&self, id: u64, result: Result<()>) {
+        let mut jobs = self.jobs.lock().unwrap();
+        if let Some(job) = jobs.iter_mut().find(|j| j.id == id) {
+            match result {
+                Ok(()) => job.state = JobState::Done,
+                Err(e) => {
+                    job.state = JobState::Failed;
+                    job.error = Some(format!("{e:#}"));
+                }
+            }
+        }
+        forget_finished_jobs(&mut jobs);
+    }
+}
+
+/// Removes the oldest finished jobs beyond MAX_FINISHED_JOBS.
+fn forget_finished_jobs(jobs: &mut Vec<Job>) {
+    let is_finished = |job: &Job| {
+        matches!(
+            job.state,
+            JobState::Done | JobState::Failed | JobState::Canceled
+        )
+    };
+    let mut excess = jobs
+        .iter()
+        .filter(|j| is_finished(j))
+        .count()
+        .saturating_sub(MAX_FINISHED_JOBS);
+    jobs.retain(|job| {
+        if excess > 0 && is_finished(job) {
+            excess -= 1;
+            return false;
+        }
+        true
+    });
+}
+
+/// Owns the connection to the printer, and prints the submitted jobs in order.
+pub struct PrintServer {
+    server: Server,
+    queue: Arc<JobQueue>,
+}
+impl PrintServer {
+    pub fn new(
+        listen: &str,
+        printer: &str,
+        width: Option<usize>,
+        tape_kind: Option<TapeKind>,
+    ) -> Result<Self> {
+        let server = Server::http(listen)
+            .map_err(|e| anyhow!("{e}"))
+            .context(anyhow!("Failed to listen on {listen}"))?;
+        let queue = Arc::new(JobQueue::default());
+        {
+            let queue = queue.clone();
+            let printer = printer.to_string();
+            thread::spawn(move || loop {
+                let (id, content, length) = queue.next_job();
+                let result = print_job(&printer, width, tape_kind, &content, &length);
+                if let Err(e) = &result {
+                    eprintln!("Job {id} failed: {e:#}");
+                }
+                queue.finish(id, result);
+            });
+        }
+        Ok(Self { server, queue })
+    }
+    pub fn addr(&self) -> Option<SocketAddr> {
+        self.server.server_addr().to_ip()
+    }
+    /// Handles the requests until the server is dropped.
+    pub fn run(&self) {
+        for mut req in self.server.incoming_requests() {
+            let (status, body) = match self.handle(&mut req) {
+                Ok((status, body)) => (status, body),
+                Err(e) => (400, serde_json::json!({ "error": format!("{e:#}") })),
+            };
+            let res = Response::from_string(body.to_string())
+                .with_status_code(status)
+                .with_header(
+                    Header::from_bytes("Content-Type", "application/json").expect("a valid header"),
+                );
+            if let Err(e) = req.respond(res) {
+                eprintln!("Failed to respond: {e}");
+            }
+        }
+    }
+    fn handle(&self, req: &mut Request) -> Result<(u16, serde_json::Value)> {
+        let path = req.url().split('?').next().unwrap_or_default().to_string();
+        let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        let not_found = (404, serde_json::json!({ "error": "Not found" }));
+        Ok(match (req.method(), segments.as_slice()) {
+            (Method::Post, ["jobs"]) => {
+                let (content, length) = parse_submission(req)?;
+                (
+                    201,
+                    serde_json::to_value(self.queue.submit(content, length))?,
+                )
+            }
+            (Method::Get, ["jobs"]) => (200, serde_json::to_value(self.queue.list())?),
+            (Method::Get, ["jobs", id]) => match self.queue.get(id.parse()?) {
+                Some(info) => (200, serde_json::to_value(info)?),
+                None => not_found,
+            },
+            (Method::Delete, ["jobs", id]) => match self.queue.cancel(id.parse()?) {
+                Some(Ok(info)) => (200, serde_json::to_value(info)?),
+                Some(Err(state)) => (
+                    409,
+                    serde_json::json!({
+                        "error": format!("The job is {state:?} and can not be canceled")
+                    }),
+                ),
+                None => not_found,
+            },
+            _ => not_found,
+        })
+    }
+}
+
+fn parse_submission(req: &mut Request) -> Result<(Content, LengthOptions)> {
+    let is_png = req
+        .headers()
+        .iter()
+        .any(|h| h.field.equiv("Content-Type") && h.value.as_str().starts_with("image/png"));
+    let mut body = Vec::new();
+    req.as_reader()
+        .take(MAX_JOB_BYTES + 1)
+        .read_to_end(&mut body)?;
+    if body.len() as u64 > MAX_JOB_BYTES {
+        return Err(anyhow!("The job is larger than {MAX_JOB_BYTES} bytes"));
+    }
+    if is_png {
+        return Ok((Content::Image(decode_png(&body)?), LengthOptions::default()));
+    }
+    let job: JobRequest = serde_json::from_slice(&body).context("Invalid job request")?;
+    let content = match (job.qr_text, job.text) {
+        (Some(text), None) => Content::QrText(text),
+        (None, Some(text)) => Content::Text(text),
+        _ => return Err(anyhow!("Please specify either qr_text or text")),
+    };
+    let length = LengthOptions {
+        length: job.length.as_deref().map(str::parse).transpose()?,
+        min_length: job.min_length.as_deref().map(str::parse).transpose()?,
+        margin_left: job.margin_left.as_deref().map(str::parse).transpose()?,
+        margin_right: job.margin_right.as_deref().map(str::parse).transpose()?,
+        align: job
+            .align
+            .as_deref()
+            .map(str::parse)
+            .transpose()?
+            .unwrap_or_default(),
+    };
+    Ok((content, length))
+}
+
+fn print_job(
+    printer: &str,
+    width: Option<usize>,
+    tape_kind: Option<TapeKind>,
+    content: &Content,
+    length: &LengthOptions,
+) -> Result<()> {
+    // The tape may be replaced between jobs, so it is checked every time
+    let geometry = detect_geometry(Some(printer), width, tape_kind)?;
+    let td = content.render(geometry.printable_px())?;
+    let td = length.apply(&td)?;
+    let job = JobEncoder::new(&td, &geometry)?;
+    let mut session = PrintSession::open(printer)?;
+    let status = match session.print_stream(job) {
+        Ok(status) => status,
+        // The printer should not be left in the session for the next job
+        Err(e) => return Err(session.abort(e)),
+    };
+    session.close()?;
+    match status {
+        PrinterStatus::SomeTape(..) | PrinterStatus::UnknownTape(..) => Ok(()),
+        status => Err(anyhow!("Unexpected state after printing: {status:?}")),
+    }
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Run a print queue with an HTTP API
+#[argh(subcommand, name = "serve")]
+pub struct ServeArgs {
+    /// an IPv4 address for the printer (or host:port, e.g. for an emulator)
+    #[argh(option)]
+    printer: String,
+    /// address to listen on for the HTTP API (default: 127.0.0.1:8080)
+    #[argh(option)]
+    listen: Option<String>,
+    /// tape width in mm (default: auto)
+    #[argh(option)]
+    width: Option<usize>,
+    /// kind of the tape: standard, clear, iron-on, magnet or tube (default: auto)
+    #[argh(option)]
+    tape_kind: Option<TapeKind>,
+}
+
+pub fn do_serve(args: &ServeArgs) -> Result<()> {
+    let server = PrintServer::new(
+        args.listen.as_deref().unwrap_or("127.0.0.1:8080"),
+        &args.printer,
+        args.width,
+        args.tape_kind,
+    )?;
+    println!(
+        "Serving the print queue for {} at http://{}/jobs",
+        args.printer,
+        server
+            .addr()
+            .map_or("(unknown)".to_string(), |a| a.to_string())
+    );
+    server.run();
+    Ok(())
+}
+
+#[test]
+fn serve_with_emulator() {
+    use crate::emulator::Emulator;
+    use crate::Tape;
+    use std::io::Read;
+    use std::io::Write;
+    use std::net::TcpStream;
+    use std::time::Duration;
+    use std::time::Instant;
+
+    fn request(addr: SocketAddr, method: &str, path: &str, body: &str) -> (u16, serde_json::Value) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "{method} {path} HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )
+        .unwrap();
+        let mut res = String::new();
+        stream.read_to_string(&mut res).unwrap();
+        let (head, body) = res.split_once("\r\n\r\n").unwrap();
+        let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+        (status, serde_json::from_str(body).unwrap())
+    }
+
+    let emulator = Emulator::start("127.0.0.1:0", Tape::W12, None).unwrap();
+    let server = PrintServer::new("127.0.0.1:0", &emulator.addr().to_string(), None, None).unwrap();
+    let addr = server.addr().unwrap();
+    thread::spawn(move || server.run());
+
+    let (status, job) = request(addr, "POST", "/jobs", r#"{"qr_text": "hello"}"#);
+    assert_eq!(status, 201);
+    assert_eq!(job["id"], 1);
+    let (status, _) = request(addr, "POST", "/jobs", r#"{"qr_text": "a", "text": "b"}"#);
+    assert_eq!(status, 400);
+    let (status, _) = request(addr, "DELETE", "/jobs/2", "");
+    assert_eq!(status, 404);
+
+    let deadline = Instant::now() + Duration::from_secs(30);
+    loop {
+        let (status, job) = request(addr, "GET", "/jobs/1", "");
+        assert_eq!(status, 200);
+        if job["state"] == "done" {
+            break;
+        }
+        assert!(job["state"] != "failed", "{job}");
+        assert!(Instant::now() < deadline, "timed out: {job}");
+        thread::sleep(Duration::from_millis(200));
+    }
+    let (_, jobs) = request(addr, "GET", "/jobs", "");
+    assert_eq!(jobs.as_array().unwrap().len(), 1);
+    // the session is recorded when the connection is closed
+    while emulator.sessions().is_empty() {
+        assert!(Instant::now() < deadline);
+        thread::sleep(Duration::from_millis(100));
+    }
+    let data = &emulator.sessions()[0];
+    assert!(data.starts_with(&[0x1b, 0x7b]));
+    assert!(data.ends_with(&[0x0c, 0x1b, 0x7b, 0x03, 0x40, 0x40, 0x7d]));
+}
+
+#[test]
+fn job_history() {
+    let queue = JobQueue::default();
+    let submit =
+        |text: &str| queue.submit(Content::Text(text.to_string()), LengthOptions::default());
+    // the worker takes the content, and nothing is left when the job is done
+    let job = submit("printed");
+    let (id, content, _) = queue.next_job();
+    assert_eq!((id, content.summary()), (job.id, job.summary.clone()));
+    queue.finish(id, Ok(()));
+    let done = queue.get(id).unwrap();
+    assert_eq!((done.state, done.summary), (JobState::Done, job.summary));
+    assert!(queue.jobs.lock().unwrap()[0].content.is_none());
+
+    // only the last MAX_FINISHED_JOBS finished jobs are kept
+    let queued = submit("queued");
+    for i in 0..MAX_FINISHED_JOBS {
+        let job = submit(&i.to_string());
+        queue.cancel(job.id).unwrap().unwrap();
+    }
+    let jobs = queue.list();
+    assert_eq!(jobs.len(), MAX_FINISHED_JOBS + 1);
+    assert!(queue.get(id).is_none());
+    // a queued job is not forgotten, and the ids are not reused
+    assert_eq!(jobs[0].id, queued.id);
+    assert_eq!(submit("new").id, MAX_FINISHED_JOBS as u64 + 3);
+    assert!(queue
+        .jobs
+        .lock()
+        .unwrap()
+        .iter()
+        .all(|j| (j.state == JobState::Queued) == j.content.is_some()));
+}
@@ -0,0 +1,209 @@
+use crate::display::TapeDisplay;
+use crate::preview::shaded;
+use crate::preview::PreviewOptions;
+use crate::preview::TapeArea;
+use crate::print::px_to_mm;
+use crate::print::DPI;
+use crate::TapeGeometry;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+fn css_color(c: [u8; 3]) -> String {
+    format!("#{:02x}{:02x}{:02x}", c[0], c[1], c[2])
+}
+
+/// Returns the dots in the row y as horizontal runs of (x, len).
+fn runs_in_row(td: &TapeDisplay, y: usize) -> Vec<(usize, usize)> {
+    let mut runs = Vec::new();
+    let mut x = 0;
+    while x < td.width {
+        if !td.get_pixel(x, y) {
+            x += 1;
+            continue;
+        }
+        let begin = x;
+        while x < td.width && td.get_pixel(x, y) {
+            x += 1;
+        }
+        runs.push((begin, x - begin));
+    }
+    runs
+}
+
+/// Generates an SVG document of td in its physical size (1 unit = 1 dot at 360 DPI).
+/// Dots are emitted as run-length rectangles so that the result is exactly
+/// what will be printed, at any zoom level.
+pub fn gen_svg(td: &TapeDisplay, geometry: &TapeGeometry, opts: &PreviewOptions) -> String {
+    let (area, ink, background) = if let Some(colors) = opts.colors {
+        (
+            TapeArea::new(td, geometry),
+            colors.ink.rgb(),
+            Some(colors.tape.rgb()),
+        )
+    } else {
+        let area = TapeArea {
+            width: td.width,
+            height: td.height,
+            ofs_x: 0,
+            ofs_y: 0,
+        };
+        (area, [0, 0, 0], None)
+    };
+    let (ofs_x, ofs_y) = (area.ofs_x, area.ofs_y);
+    let mut svg = String::new();
+    let _ = writeln!(svg, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{:.3}mm" height="{:.3}mm" viewBox="0 0 {} {}" shape-rendering="crispEdges">"#,
+        px_to_mm(area.width),
+        px_to_mm(area.height),
+        area.width,
+        area.height,
+    );
+    if let Some(background) = background {
+        let margin = css_color(shaded(background));
+        let _ = writeln!(
+            svg,
+            r#"<rect width="{}" height="{}" fill="{margin}"/>"#,
+            area.width, area.height
+        );
+        let _ = writeln!(
+            svg,
+            r#"<rect x="{ofs_x}" y="{ofs_y}" width="{}" height="{}" fill="{}"/>"#,
+            td.width,
+            td.height,
+            css_color(background)
+        );
+    } else {
+        let _ = writeln!(
+            svg,
+            r##"<rect width="{}" height="{}" fill="#ffffff"/>"##,
+            area.width, area.height
+        );
+    }
+    let _ = writeln!(
+        svg,
+        r#"<g fill="{}" transform="translate({ofs_x} {ofs_y})">"#,
+        css_color(ink)
+    );
+    // Runs at the same position in consecutive rows are merged into one rect.
+    // (x, len, y_begin)
+    let mut open_runs: Vec<(usize, usize, usize)> = Vec::new();
+    for y in 0..=td.height {
+        let runs = if y < td.height {
+            runs_in_row(td, y)
+        } else {
+            Vec::new()
+        };
+        let mut next_runs = Vec::new();
+        for (x, len, y_begin) in open_runs {
+            if runs.contains(&(x, len)) {
+                next_runs.push((x, len, y_begin));
+            } else {
+                let h = y - y_begin;
+                let _ = writeln!(
+                    svg,
+                    r#"<rect x="{x}" y="{y_begin}" width="{len}" height="{h}"/>"#
+                );
+            }
+        }
+        for (x, len) in runs {
+            if !next_runs.iter().any(|r| (r.0, r.1) == (x, len)) {
+                next_runs.push((x, len, y));
+            }
+        }
+        open_runs = next_runs;
+    }
+    let _ = writeln!(svg, "</g>");
+    let _ = writeln!(svg, "</svg>");
+    svg
+}
+
+const CSS_DPI: f64 = 96.0;
+
+/// Options to parse SVG images. Loading the system fonts is slow, so it is done only once.
+fn svg_options() -> &'static usvg::Options {
+    static OPTIONS: OnceLock<usvg::Options> = OnceLock::new();
+    OPTIONS.get_or_init(|| {
+        let mut opt = usvg::Options {
+            dpi: CSS_DPI,
+            ..Default::default()
+        };
+        opt.fontdb.load_system_fonts();
+        opt
+    })
+}
+
+/// Rasterizes an SVG image at 360 DPI. The physical size written in the SVG
+/// is kept (user units are CSS px, i.e. 96 DPI), unless height_px is given.
+/// Pixels darker than threshold (in luminance, over a white background) will be printed.
+pub fn rasterize_svg(data: &[u8], height_px: Option<usize>, threshold: u8) -> Result<TapeDisplay> {
+    let tree =
+        usvg::Tree::from_data(data, &svg_options().to_ref()).context("Failed to parse the SVG")?;
+    let fit_to = if let Some(h) = height_px {
+        usvg::FitTo::Height(h as u32)
+    } else {
+        usvg::FitTo::Zoom((DPI as f64 / CSS_DPI) as f32)
+    };
+    let size = fit_to
+        .fit_to(tree.svg_node().size.to_screen_size())
+        .context("Failed to determine the size of the SVG")?;
+    let mut pixmap = tiny_skia::Pixmap::new(size.width(), size.height())
+        .context("Failed to allocate a pixmap for the SVG")?;
+    resvg::render(
+        &tree,
+        fit_to,
+        tiny_skia::Transform::default(),
+        pixmap.as_mut(),
+    )
+    .context("Failed to render the SVG")?;
+    let mut td = TapeDisplay::new(size.width() as usize, size.height() as usize);
+    for (i, p) in pixmap.pixels().iter().enumerate() {
+        // pixels are premultiplied, so this is the color over a white background
+        let white = 255 - p.alpha() as u32;
+        let (r, g, b) = (
+            p.red() as u32 + white,
+            p.green() as u32 + white,
+            p.blue() as u32 + white,
+        );
+        let luma = (r * 299 + g * 587 + b * 114) / 1000;
+        td.set_pixel(i % td.width, i / td.width, luma < threshold as u32);
+    }
+    Ok(td)
+}
+
+pub fn write_svg(
+    path: &Path,
+    td: &TapeDisplay,
+    geometry: &TapeGeometry,
+    opts: &PreviewOptions,
+) -> Result<()> {
+    fs::write(path, gen_svg(td, geometry, opts)).context(anyhow!("Failed to write {path:?}"))
+}
+
+#[test]
+fn rasterize() {
+    // 1 inch x 0.5 inch, black on the left half and gray on the right half
+    let svg = br##"<svg xmlns="http://www.w3.org/2000/svg" width="96" height="48">
+        <rect x="0" y="0" width="48" height="48" fill="#000000"/>
+        <rect x="48" y="0" width="48" height="48" fill="#808080"/>
+    </svg>"##;
+    // the physical size is kept at 360 DPI
+    let td = rasterize_svg(svg, None, 128).unwrap();
+    assert_eq!((td.width, td.height), (360, 180));
+    assert!(td.get_pixel(10, 90));
+    assert!(!td.get_pixel(350, 90));
+    // a higher threshold prints the gray too
+    let td = rasterize_svg(svg, None, 200).unwrap();
+    assert!(td.get_pixel(350, 90));
+    // fitted to the given height (--svg-fit)
+    let td = rasterize_svg(svg, Some(36), 128).unwrap();
+    assert_eq!((td.width, td.height), (72, 36));
+    assert!(td.get_pixel(0, 0) && !td.get_pixel(71, 35));
+    assert!(rasterize_svg(b"not an svg", None, 128).is_err());
+}
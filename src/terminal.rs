@@ -0,0 +1,316 @@
+use crate::display::TapeDisplay;
+use crate::preview::encode_preview_png;
+use crate::preview::PreviewOptions;
+use crate::TapeGeometry;
+use anyhow::anyhow;
+use anyhow::Result;
+use std::env;
+use std::fs::File;
+use std::io::Write;
+use std::process::Command;
+use std::process::Stdio;
+use std::str::FromStr;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TermPreviewMode {
+    /// kitty graphics if the terminal looks like supporting it, half blocks otherwise
+    Auto,
+    /// 1x2 dots per character with "▀", "▄" and "█"
+    HalfBlock,
+    /// 2x4 dots per character with braille patterns
+    Braille,
+    Sixel,
+    Kitty,
+}
+impl FromStr for TermPreviewMode {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "auto" => TermPreviewMode::Auto,
+            "halfblock" => TermPreviewMode::HalfBlock,
+            "braille" => TermPreviewMode::Braille,
+            "sixel" => TermPreviewMode::Sixel,
+            "kitty" => TermPreviewMode::Kitty,
+            _ => {
+                return Err(anyhow!(
+                    "Unknown terminal preview mode: {s} (auto, halfblock, braille, sixel or kitty)"
+                ))
+            }
+        })
+    }
+}
+
+fn kitty_is_supported() -> bool {
+    env::var_os("KITTY_WINDOW_ID").is_some()
+        || env::var("TERM").is_ok_and(|t| t.contains("kitty"))
+        || env::var("TERM_PROGRAM").is_ok_and(|t| t == "WezTerm" || t == "ghostty")
+}
+
+fn terminal_columns() -> usize {
+    if let Some(cols) = env::var("COLUMNS")
+        .ok()
+        .and_then(|c| c.parse().ok())
+        .filter(|c| *c > 0)
+    {
+        return cols;
+    }
+    // "stty size" prints "<rows> <cols>" for the controlling terminal
+    File::open("/dev/tty")
+        .ok()
+        .and_then(|tty| {
+            Command::new("stty")
+                .arg("size")
+                .stdin(Stdio::from(tty))
+                .stderr(Stdio::null())
+                .output()
+                .ok()
+        })
+        .and_then(|out| {
+            String::from_utf8_lossy(&out.stdout)
+                .split_whitespace()
+                .nth(1)
+                .and_then(|c| c.parse().ok())
+        })
+        .filter(|c| *c > 0)
+        .unwrap_or(80)
+}
+
+/// Shrinks td by an integer factor so that it fits in max_width. A pixel in
+/// the result is on if any of the pixels it covers is on, so thin lines are kept.
+fn downscaled_to_fit(td: &TapeDisplay, max_width: usize) -> TapeDisplay {
+    let r = std::cmp::max(td.width.div_ceil(std::cmp::max(max_width, 1)), 1);
+    if r == 1 {
+        return td.clone();
+    }
+    let mut new = TapeDisplay::new(td.width.div_ceil(r), td.height.div_ceil(r));
+    for y in 0..td.height {
+        for x in 0..td.width {
+            if td.get_pixel(x, y) {
+                new.set_pixel(x / r, y / r, true);
+            }
+        }
+    }
+    new
+}
+
+fn render_half_blocks(td: &TapeDisplay) -> String {
+    let mut s = String::new();
+    for y in (0..td.height).step_by(2) {
+        for x in 0..td.width {
+            s.push(match (td.get_pixel(x, y), td.get_pixel(x, y + 1)) {
+                (false, false) => ' ',
+                (true, false) => '▀',
+                (false, true) => '▄',
+                (true, true) => '█',
+            });
+        }
+        s.push('\n');
+    }
+    s
+}
+
+fn render_braille(td: &TapeDisplay) -> String {
+    // bit index of each dot in a 2x4 cell, as defined by Unicode (U+2800-U+28FF)
+    const DOT_BITS: [[u32; 2]; 4] = [[0, 3], [1, 4], [2, 5], [6, 7]];
+    let mut s = String::new();
+    for y in (0..td.height).step_by(4) {
+        for x in (0..td.width).step_by(2) {
+            let mut bits = 0;
+            for (dy, row) in DOT_BITS.iter().enumerate() {
+                for (dx, bit) in row.iter().enumerate() {
+                    if td.get_pixel(x + dx, y + dy) {
+                        bits |= 1 << bit;
+                    }
+                }
+            }
+            s.push(char::from_u32(0x2800 + bits).unwrap_or(' '));
+        }
+        s.push('\n');
+    }
+    s
+}
+
+/// The width of a character cell in px is not known without asking the
+/// terminal, so a typical one is assumed to fit images in the terminal.
+const PX_PER_COLUMN: usize = 8;
+
+fn render_sixel(td: &TapeDisplay) -> String {
+    let mut s = String::new();
+    s += "\x1bPq";
+    s += &format!("\"1;1;{};{}", td.width, td.height);
+    // color 0: paper, color 1: ink
+    s += "#0;2;100;100;100#1;2;0;0;0";
+    for band in (0..td.height).step_by(6) {
+        for (color, ink) in [(0, false), (1, true)] {
+            s += &format!("#{color}");
+            let mut x = 0;
+            while x < td.width {
+                let sixel = |x: usize| {
+                    (0..6).fold(0u8, |acc, dy| {
+                        let y = band + dy;
+                        if y < td.height && td.get_pixel(x, y) == ink {
+                            acc | (1 << dy)
+                        } else {
+                            acc
+                        }
+                    })
+                };
+                let v = sixel(x);
+                let mut run = 1;
+                while x + run < td.width && sixel(x + run) == v {
+                    run += 1;
+                }
+                let c = (0x3f + v) as char;
+                if run > 3 {
+                    s += &format!("!{run}{c}");
+                } else {
+                    s.extend(std::iter::repeat_n(c, run));
+                }
+                x += run;
+            }
+            // go back to the beginning of the band for the next color
+            s.push('$');
+        }
+        s.push('-');
+    }
+    s += "\x1b\\";
+    s
+}
+
+fn base64(data: &[u8]) -> String {
+    const TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut s = String::new();
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let v = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                s.push(TABLE[(v >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                s.push('=');
+            }
+        }
+    }
+    s
+}
+
+fn render_kitty(
+    td: &TapeDisplay,
+    geometry: &TapeGeometry,
+    opts: &PreviewOptions,
+    cols: usize,
+) -> Result<String> {
+    let mut png = Vec::new();
+    encode_preview_png(&mut png, td, geometry, opts)?;
+    // An image wider than the terminal is scaled down by the terminal to the
+    // given number of columns (the width in the IHDR chunk is at offset 16)
+    let png_width = u32::from_be_bytes(png[16..20].try_into()?) as usize;
+    let columns = if png_width > cols * PX_PER_COLUMN {
+        format!(",c={cols}")
+    } else {
+        String::new()
+    };
+    let encoded = base64(&png);
+    // The payload should be sent in chunks of 4096 bytes at most
+    let chunks: Vec<&[u8]> = encoded.as_bytes().chunks(4096).collect();
+    let mut s = String::new();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = (i + 1 < chunks.len()) as u8;
+        if i == 0 {
+            s += &format!("\x1b_Gf=100,a=T{columns},m={more};");
+        } else {
+            s += &format!("\x1b_Gm={more};");
+        }
+        s += std::str::from_utf8(chunk)?;
+        s += "\x1b\\";
+    }
+    s.push('\n');
+    Ok(s)
+}
+
+/// Prints td to stdout so that a label can be checked without opening the preview image.
+pub fn print_to_terminal(
+    td: &TapeDisplay,
+    geometry: &TapeGeometry,
+    mode: TermPreviewMode,
+    opts: &PreviewOptions,
+) -> Result<()> {
+    let mode = match mode {
+        TermPreviewMode::Auto if kitty_is_supported() => TermPreviewMode::Kitty,
+        TermPreviewMode::Auto => TermPreviewMode::HalfBlock,
+        mode => mode,
+    };
+    let cols = terminal_columns();
+    let s = match mode {
+        TermPreviewMode::HalfBlock => render_half_blocks(&downscaled_to_fit(td, cols)),
+        TermPreviewMode::Braille => render_braille(&downscaled_to_fit(td, cols * 2)),
+        TermPreviewMode::Sixel => render_sixel(&downscaled_to_fit(td, cols * PX_PER_COLUMN)),
+        TermPreviewMode::Kitty => render_kitty(td, geometry, opts, cols)?,
+        TermPreviewMode::Auto => unreachable!(),
+    };
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(s.as_bytes())?;
+    stdout.flush()?;
+    Ok(())
+}
+
+#[test]
+fn terminal_output() {
+    let mut td = TapeDisplay::new(3, 4);
+    td.set_pixel(0, 0, true);
+    td.set_pixel(1, 1, true);
+    td.set_pixel(2, 0, true);
+    td.set_pixel(2, 1, true);
+    td.set_pixel(0, 3, true);
+    assert_eq!(render_half_blocks(&td), "▀▄█\n▄  \n");
+    // dots 1, 5 and 7 in the first cell, dots 1 and 2 in the second
+    assert_eq!(render_braille(&td), "\u{2851}\u{2803}\n");
+
+    // an odd dot keeps the pixel on when it is shrunk
+    let small = downscaled_to_fit(&td, 2);
+    assert_eq!((small.width, small.height), (2, 2));
+    assert!(small.get_pixel(0, 0) && small.get_pixel(1, 0) && small.get_pixel(0, 1));
+    assert!(!small.get_pixel(1, 1));
+    assert_eq!(downscaled_to_fit(&td, 0).width, 1);
+
+    // a 2x7 bitmap is sent in 2 bands of 6 rows, painting the paper then the ink
+    let mut td = TapeDisplay::new(2, 7);
+    td.set_pixel(0, 0, true);
+    td.set_pixel(1, 6, true);
+    assert_eq!(
+        render_sixel(&td),
+        "\x1bPq\"1;1;2;7#0;2;100;100;100#1;2;0;0;0#0}~$#1@?$-#0@?$#1?@$-\x1b\\"
+    );
+    // repeated sixels are run-length encoded
+    assert_eq!(
+        render_sixel(&TapeDisplay::new(5, 1)),
+        "\x1bPq\"1;1;5;1#0;2;100;100;100#1;2;0;0;0#0!5@$#1!5?$-\x1b\\"
+    );
+
+    // kitty graphics are scaled down to the terminal only if they are wider
+    let geometry = crate::Tape::W12.geometry();
+    let opts = PreviewOptions::default();
+    let td = TapeDisplay::new(80, geometry.printable_px());
+    let kitty = render_kitty(&td, &geometry, &opts, 5).unwrap();
+    assert!(kitty.starts_with("\x1b_Gf=100,a=T,c=5,m=0;"));
+    assert!(kitty.ends_with("\x1b\\\n"));
+    let kitty = render_kitty(&td, &geometry, &opts, 80).unwrap();
+    assert!(kitty.starts_with("\x1b_Gf=100,a=T,m=0;"));
+
+    // RFC 4648 test vectors
+    for (data, encoded) in [
+        ("", ""),
+        ("f", "Zg=="),
+        ("fo", "Zm8="),
+        ("foo", "Zm9v"),
+        ("foob", "Zm9vYg=="),
+        ("fooba", "Zm9vYmE="),
+        ("foobar", "Zm9vYmFy"),
+    ] {
+        assert_eq!(base64(data.as_bytes()), encoded);
+    }
+}
@@ -0,0 +1,300 @@
+//! Renders every layout for every tape, and compares the bitmaps and the data
+//! sent to the printer (gzipped) with the files in tests/golden. Layouts which
+//! fail on a tape are compared by their error messages. The data is also
+//! decoded, and should give the same bitmap.
+//!
+//! After an intended change, run `BLESS=1 cargo test --test golden` to update
+//! the files. On a mismatch, the actual bitmap and a diff image (red: missing
+//! pixels, blue: extra pixels) are written to target/golden-diff.
+
+use argh::FromArgs;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use sr5900p::analyzer::decode_tcp_data;
+use sr5900p::display::TapeDisplay;
+use sr5900p::print::gen_tcp_data;
+use sr5900p::print::render_label;
+use sr5900p::print::PrintArgs;
+use sr5900p::Tape;
+use std::fs;
+use std::fs::File;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+const TAPES: [Tape; 7] = [
+    Tape::W4,
+    Tape::W6,
+    Tape::W9,
+    Tape::W12,
+    Tape::W18,
+    Tape::W24,
+    Tape::W36,
+];
+
+/// (name, options of the print command)
+const LAYOUTS: &[(&str, &[&str])] = &[
+    ("test_pattern", &["--test-pattern"]),
+    ("qr_text", &["--qr-text", "Hello, world!"]),
+    (
+        "qr_text_length",
+        &[
+            "--qr-text",
+            "SRV-0001",
+            "--length",
+            "120mm",
+            "--align",
+            "left",
+        ],
+    ),
+    (
+        "datamatrix",
+        &["--qr-text", "SRV-0001", "--symbology", "datamatrix"],
+    ),
+    (
+        "datamatrix_rect",
+        &["--qr-text", "SRV-0001", "--symbology", "datamatrix-rect"],
+    ),
+    ("aztec", &["--qr-text", "SRV-0001", "--symbology", "aztec"]),
+    ("qr_text_small", &["--qr-text-small", "Hello"]),
+    (
+        "wifi",
+        &["--wifi-ssid", "Guest", "--wifi-password", "secret"],
+    ),
+    (
+        "sequence",
+        &["--seq-format", "SRV-{:04}", "--seq-count", "3"],
+    ),
+    ("banner", &["--banner", "HELLO"]),
+    (
+        "svg_image",
+        &[
+            "--svg-image",
+            concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/logo.svg"),
+            "--svg-fit",
+            "--svg-text",
+            "SVG",
+        ],
+    ),
+    (
+        "cable_wrap",
+        &["--cable-wrap", "sw01", "--cable-diameter", "6mm"],
+    ),
+    (
+        "cable_flag",
+        &[
+            "--flag-text",
+            "sw01",
+            "--flag-qr",
+            "https://example.com/sw01",
+            "--cable-diameter",
+            "6mm",
+        ],
+    ),
+];
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+fn diff_dir() -> PathBuf {
+    std::env::var_os("CARGO_TARGET_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join("target"))
+        .join("golden-diff")
+}
+
+/// Writes a 1-bit grayscale PNG, which is much smaller than the previews
+fn write_png(path: &Path, td: &TapeDisplay) {
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(path).unwrap()),
+        td.width as u32,
+        td.height as u32,
+    );
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::One);
+    encoder.set_compression(png::Compression::Best);
+    let data: Vec<u8> = td
+        .framebuffer
+        .iter()
+        .flat_map(|row| {
+            row.chunks(8).map(|c| {
+                c.iter()
+                    .enumerate()
+                    .fold(0xff, |b, (i, p)| if *p { b & !(0x80 >> i) } else { b })
+            })
+        })
+        .collect();
+    encoder
+        .write_header()
+        .unwrap()
+        .write_image_data(&data)
+        .unwrap();
+}
+
+/// Reads a bitmap written by write_png or a preview.png of the print command
+fn read_png(path: &Path) -> TapeDisplay {
+    let mut decoder = png::Decoder::new(File::open(path).unwrap());
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info().unwrap();
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).unwrap();
+    let samples = info.color_type.samples();
+    let mut td = TapeDisplay::new(info.width as usize, info.height as usize);
+    for y in 0..td.height {
+        for x in 0..td.width {
+            td.set_pixel(x, y, buf[y * info.line_size + x * samples] < 128);
+        }
+    }
+    td
+}
+
+/// Writes an image of the differences, and returns the number of differing px
+fn write_diff(path: &Path, expected: &TapeDisplay, actual: &TapeDisplay) -> usize {
+    let width = std::cmp::max(expected.width, actual.width);
+    let height = std::cmp::max(expected.height, actual.height);
+    let get = |td: &TapeDisplay, x, y| x < td.width && y < td.height && td.get_pixel(x, y);
+    let mut count = 0;
+    let mut data = Vec::new();
+    for y in 0..height {
+        for x in 0..width {
+            let (e, a) = (get(expected, x, y), get(actual, x, y));
+            if e != a {
+                count += 1;
+            }
+            data.extend(match (e, a) {
+                (true, true) => [0, 0, 0],
+                (false, false) => [255, 255, 255],
+                (true, false) => [255, 0, 0],
+                (false, true) => [0, 0, 255],
+            });
+        }
+    }
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(path).unwrap()),
+        width as u32,
+        height as u32,
+    );
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .unwrap()
+        .write_image_data(&data)
+        .unwrap();
+    count
+}
+
+fn write_gz(path: &Path, data: &[u8]) {
+    let mut encoder = GzEncoder::new(File::create(path).unwrap(), Compression::best());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap();
+}
+
+fn read_gz(path: &Path) -> Vec<u8> {
+    let mut data = Vec::new();
+    if let Ok(file) = File::open(path) {
+        GzDecoder::new(file).read_to_end(&mut data).unwrap();
+    }
+    data
+}
+
+/// Returns the differences from the golden files, or updates them if bless
+fn check(name: &str, tape: Tape, args: &[&str], bless: bool) -> Vec<String> {
+    let golden = golden_dir();
+    let png_path = golden.join(format!("{name}.png"));
+    let bin_path = golden.join(format!("{name}.bin.gz"));
+    let err_path = golden.join(format!("{name}.err"));
+    let geometry = tape.geometry();
+    let args = PrintArgs::from_args(&["print"], args)
+        .unwrap_or_else(|e| panic!("Invalid args for {name}: {}", e.output));
+    let result =
+        render_label(&args, &geometry).and_then(|td| Ok((gen_tcp_data(&td, &geometry)?, td)));
+
+    if bless {
+        for path in [&png_path, &bin_path, &err_path] {
+            let _ = fs::remove_file(path);
+        }
+        match result {
+            Ok((data, td)) => {
+                write_png(&png_path, &td);
+                write_gz(&bin_path, &data);
+            }
+            Err(e) => fs::write(&err_path, format!("{e}\n")).unwrap(),
+        }
+        return Vec::new();
+    }
+
+    let (data, td) = match (result, err_path.exists()) {
+        (Err(e), true) => {
+            let expected = fs::read_to_string(&err_path).unwrap();
+            if expected.trim_end() == e.to_string() {
+                return Vec::new();
+            }
+            return vec![format!("{name}: the error changed to: {e}")];
+        }
+        (Err(e), false) => return vec![format!("{name}: failed: {e}")],
+        (Ok(_), true) => return vec![format!("{name}: succeeded, but an error is expected")],
+        (Ok(r), false) => r,
+    };
+    if !png_path.exists() {
+        return vec![format!("{name}: no golden files (run with BLESS=1)")];
+    }
+    let mut failures = Vec::new();
+    let decoded = decode_tcp_data(&data).and_then(|job| job.to_display(geometry.head_offset_dots));
+    match decoded {
+        Ok(decoded) if decoded.framebuffer == td.framebuffer => (),
+        Ok(_) => failures.push(format!("{name}: the data does not decode to the bitmap")),
+        Err(e) => failures.push(format!("{name}: the data can not be decoded: {e}")),
+    }
+    let diff = diff_dir();
+    fs::create_dir_all(&diff).unwrap();
+    let expected = read_png(&png_path);
+    if expected.framebuffer != td.framebuffer {
+        write_png(&diff.join(format!("{name}.png")), &td);
+        let diff_path = diff.join(format!("{name}.diff.png"));
+        let count = write_diff(&diff_path, &expected, &td);
+        failures.push(format!(
+            "{name}: the bitmap differs ({}x{} -> {}x{}, {count} px), see {diff_path:?}",
+            expected.width, expected.height, td.width, td.height
+        ));
+    }
+    let expected = read_gz(&bin_path);
+    if expected != data {
+        let ofs = expected
+            .iter()
+            .zip(&data)
+            .position(|(a, b)| a != b)
+            .unwrap_or(std::cmp::min(expected.len(), data.len()));
+        let actual_path = diff.join(format!("{name}.bin"));
+        fs::write(&actual_path, &data).unwrap();
+        failures.push(format!(
+            "{name}: the data differs at {ofs:#x} ({} -> {} bytes), see {actual_path:?}",
+            expected.len(),
+            data.len()
+        ));
+    }
+    failures
+}
+
+#[test]
+fn golden_layouts() {
+    let bless = std::env::var_os("BLESS").is_some();
+    let _ = fs::remove_dir_all(diff_dir());
+    let mut failures = Vec::new();
+    for (layout, args) in LAYOUTS {
+        for tape in TAPES {
+            let name = format!("{layout}_{}mm", tape.width_mm());
+            failures.extend(check(&name, tape, args, bless));
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "{} golden checks failed:\n{}",
+        failures.len(),
+        failures.join("\n")
+    );
+}